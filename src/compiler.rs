@@ -1,13 +1,125 @@
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
 use std::env::var;
-use std::ffi::{c_uint, c_ulonglong};
-use llvm_sys::{core, LLVMIntPredicate, prelude};
-use llvm_sys::prelude::{LLVMBool, LLVMTypeRef, LLVMValueRef};
+use std::ffi::{c_char, c_uint, c_ulonglong, c_void, CStr};
+use std::iter::Peekable;
+use std::ops::Deref;
+use std::rc::Rc;
+use std::str::Split;
+use llvm_sys::{core, debuginfo, linker, target, LLVMDiagnosticSeverity, LLVMIntPredicate, LLVMLinkage, LLVMRealPredicate, LLVMTypeKind, prelude};
+use llvm_sys::prelude::{LLVMBasicBlockRef, LLVMBool, LLVMContextRef, LLVMTypeRef, LLVMValueRef};
 use crate::{c_str, c_str_ptr};
-use crate::source::{ParseError, ParseET, Span};
+use crate::emit::{optimize, set_host_target, verify, OptLevel};
+use crate::source::{warning_count, ParseError, ParseET, Source, Span};
 use crate::tokens::tok_iter::TokIter;
+use crate::tokens::tokenize::{str_to_num_lit, tokenize};
+use crate::tokens::symbol::Symbol;
 use crate::tokens::tokens::{Literal, NumLit, Token, TokenType};
 
+/// `(type, value, is alloca, is unsigned, pointee)`, the pointee is the type behind a `ptr <type>` or the function type of a `fn`
+type Var = (LLVMTypeRef, LLVMValueRef, bool, bool, Option<LLVMTypeRef>);
+type VarMap = HashMap<Symbol, Var>;
+
+/// the variables of a function body, popping a scope undoes what it declared so shadowing works without copying the map
+#[derive(Default)]
+struct LocalVarMap {
+    vars: VarMap,
+    shadowed: Vec<(Symbol, Option<Var>)>,
+    scopes: Vec<usize>,
+    unsigned_params: Vec<bool>
+}
+
+impl LocalVarMap {
+    fn get(&self, name: &Symbol) -> Option<&Var> {
+        self.vars.get(name)
+    }
+
+    fn insert(&mut self, name: Symbol, var: Var) {
+        let old = self.vars.insert(name, var);
+        if !self.scopes.is_empty() {
+            self.shadowed.push((name, old));
+        }
+    }
+
+    fn push_scope(&mut self) {
+        self.scopes.push(self.shadowed.len());
+    }
+
+    fn pop_scope(&mut self) -> VarMap {
+        let start = self.scopes.pop().unwrap();
+        let mut declared = HashMap::new();
+        for (name, old) in self.shadowed.drain(start..).rev() {
+            let var = match old {
+                Some(old) => self.vars.insert(name, old),
+                None => self.vars.remove(&name)
+            };
+            // undone last to first, so the first one seen for a name is its final value
+            declared.entry(name).or_insert(var.unwrap());
+        }
+        declared
+    }
+}
+
+/// `(continue target, break target)` of every loop around the current statement, innermost last
+type LoopStack = Vec<(LLVMBasicBlockRef, LLVMBasicBlockRef)>;
+
+pub(crate) struct Module {
+    module: prelude::LLVMModuleRef,
+    ctx: LLVMContextRef
+}
+
+impl Module {
+    fn new(name: &str) -> Self {
+        unsafe {
+            let ctx = core::LLVMContextCreate();
+            Self { module: core::LLVMModuleCreateWithNameInContext(c_str_ptr!(name), ctx), ctx }
+        }
+    }
+}
+
+impl Deref for Module {
+    type Target = prelude::LLVMModuleRef;
+
+    fn deref(&self) -> &Self::Target {
+        &self.module
+    }
+}
+
+impl Drop for Module {
+    fn drop(&mut self) {
+        unsafe {
+            core::LLVMDisposeModule(self.module);
+            core::LLVMContextDispose(self.ctx);
+        }
+    }
+}
+
+struct Builder(prelude::LLVMBuilderRef);
+
+impl Builder {
+    fn at_end(ctx: LLVMContextRef, block: LLVMBasicBlockRef) -> Self {
+        unsafe {
+            let b = core::LLVMCreateBuilderInContext(ctx);
+            core::LLVMPositionBuilderAtEnd(b, block);
+            Self(b)
+        }
+    }
+}
+
+impl Deref for Builder {
+    type Target = prelude::LLVMBuilderRef;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl Drop for Builder {
+    fn drop(&mut self) {
+        unsafe { core::LLVMDisposeBuilder(self.0) }
+    }
+}
+
 macro_rules! expect_ident {
     ($tokens: ident, $expected: literal) => {
         {
@@ -16,7 +128,7 @@ macro_rules! expect_ident {
                 if ident == $expected {
                     $tokens.next();
                 } else {
-                    return Err(ParseET::ParseError($expected.to_string(), ident).at(tok.loc))
+                    return Err(ParseET::ParseError($expected.to_string(), ident.to_string()).at(tok.loc))
                 }
             } else {
                 return Err(ParseET::ParseError($expected.to_string(), format!("{:?}", tok.tt)).at(tok.loc))
@@ -39,76 +151,500 @@ macro_rules! ident_next {
     };
 }
 
-pub(crate) fn compile(mut tokens: TokIter, name: &str) -> Result<prelude::LLVMModuleRef, ParseError> {
-    let module = unsafe { core::LLVMModuleCreateWithName(c_str_ptr!(name)) };
+macro_rules! expect_particle {
+    ($tokens: ident, $expected: literal) => {
+        {
+            let tok = $tokens.this()?;
+            if let TokenType::Particle(p, _) = tok.tt {
+                if p == $expected {
+                    $tokens.next();
+                } else {
+                    return Err(ParseET::ParseError($expected.to_string(), p.to_string()).at(tok.loc))
+                }
+            } else {
+                return Err(ParseET::ParseError($expected.to_string(), format!("{:?}", tok.tt)).at(tok.loc))
+            }
+        }
+    };
+}
+
+#[derive(Default)]
+pub(crate) struct CompileOptions {
+    /// `NAME => value` pairs registered as constants before compiling
+    pub(crate) defines: HashMap<String, String>,
+    /// count the calls of every function and print them when the program exits
+    pub(crate) profile: bool,
+    /// declare the memory functions of the prelude without an `extern`
+    pub(crate) prelude: bool,
+    /// fail the compile if it reported any warning
+    pub(crate) warnings_as_errors: bool,
+    pub(crate) opt_level: OptLevel,
+}
+
+pub(crate) fn compile(tokens: TokIter, name: &str, options: &CompileOptions) -> Result<Module, ParseError> {
+    let module = Module::new(name);
+    let warnings = warning_count();
+    if !compile_into(tokens, &module, options)? {
+        return Err(ParseET::ParseError("fn main".to_string(), "nothing".to_string()).error())
+    }
+    let warnings = warning_count() - warnings;
+    if options.warnings_as_errors && warnings > 0 {
+        return Err(ParseET::WarningsAsErrors(warnings).error())
+    }
+    verify(*module)?;
+    if options.opt_level != OptLevel::None {
+        optimize(*module, options.opt_level)?;
+    }
+    Ok(module)
+}
+
+/// compiles every file into a module of its own and links them, so the `extern fn`s of one resolve to the `fn`s of another
+pub(crate) fn compile_and_link(files: Vec<(TokIter, String)>) -> Result<Module, ParseError> {
+    let mut files = files.into_iter();
+    let (tokens, name) = files.next().ok_or_else(|| ParseET::EmptyInput.error().when("linking"))?;
+    let linked = Module::new(&name);
+    let mut has_main = compile_into(tokens, &linked, &CompileOptions::default()).map_err(|e| e.when(&format!("compiling {name}")))?;
+    let ctx = context(&linked);
+    for (tokens, name) in files {
+        // linking takes the module over, so it has no `Module` that would dispose it a second time
+        let module = unsafe { core::LLVMModuleCreateWithNameInContext(c_str_ptr!(name), ctx) };
+        match compile_into(tokens, &module, &CompileOptions::default()) {
+            Ok(defines_main) => has_main |= defines_main,
+            Err(e) => {
+                unsafe { core::LLVMDisposeModule(module) }
+                return Err(e.when(&format!("compiling {name}")))
+            }
+        }
+        if let Some(duplicate) = duplicate_definition(*linked, module) {
+            unsafe { core::LLVMDisposeModule(module) }
+            return Err(ParseET::DuplicateDefinition(duplicate).error().when(&format!("linking {name}")))
+        }
+        let mut errors: Vec<String> = vec![];
+        let failed = unsafe {
+            // without a handler llvm prints link errors and exits the process
+            core::LLVMContextSetDiagnosticHandler(ctx, Some(collect_link_error), &mut errors as *mut Vec<String> as *mut c_void);
+            let failed = linker::LLVMLinkModules2(*linked, module);
+            core::LLVMContextSetDiagnosticHandler(ctx, None, std::ptr::null_mut());
+            failed
+        };
+        if failed != 0 {
+            return Err(ParseET::LinkError(errors.join("\n")).error().when(&format!("linking {name}")))
+        }
+    }
+    if !has_main {
+        return Err(ParseET::ParseError("fn main in one of the files".to_string(), "nothing".to_string()).error().when("linking"))
+    }
+    verify(*linked)?;
+    Ok(linked)
+}
+
+fn duplicate_definition(linked: prelude::LLVMModuleRef, module: prelude::LLVMModuleRef) -> Option<String> {
+    unsafe {
+        let defined = |v: LLVMValueRef| !v.is_null() && core::LLVMIsDeclaration(v) == 0
+            && !matches!(core::LLVMGetLinkage(v), LLVMLinkage::LLVMInternalLinkage | LLVMLinkage::LLVMPrivateLinkage);
+        let name = |v: LLVMValueRef| core::LLVMGetValueName2(v, &mut 0);
+        let mut f = core::LLVMGetFirstFunction(module);
+        while !f.is_null() {
+            if defined(f) && defined(core::LLVMGetNamedFunction(linked, name(f))) {
+                return Some(CStr::from_ptr(name(f)).to_string_lossy().into_owned())
+            }
+            f = core::LLVMGetNextFunction(f);
+        }
+        let mut g = core::LLVMGetFirstGlobal(module);
+        while !g.is_null() {
+            if defined(g) && defined(core::LLVMGetNamedGlobal(linked, name(g))) {
+                return Some(CStr::from_ptr(name(g)).to_string_lossy().into_owned())
+            }
+            g = core::LLVMGetNextGlobal(g);
+        }
+        None
+    }
+}
+
+extern "C" fn collect_link_error(info: prelude::LLVMDiagnosticInfoRef, errors: *mut c_void) {
+    unsafe {
+        if core::LLVMGetDiagInfoSeverity(info) != LLVMDiagnosticSeverity::LLVMDSError {
+            return
+        }
+        let msg = core::LLVMGetDiagInfoDescription(info);
+        (*(errors as *mut Vec<String>)).push(CStr::from_ptr(msg).to_string_lossy().into_owned());
+        core::LLVMDisposeMessage(msg);
+    }
+}
+
+/// returns whether the code defines `main`, without one the entry wrapper is removed so the module can be linked into one that has it
+fn compile_into(mut tokens: TokIter, module: &prelude::LLVMModuleRef, options: &CompileOptions) -> Result<bool, ParseError> {
+    // `assert_size` needs the data layout
+    set_host_target(*module)?;
+    let ctx = context(module);
     let function_name = c_str!("main");
     let function_type = unsafe {
         let mut param_types = [];
-        core::LLVMFunctionType(core::LLVMVoidType(), param_types.as_mut_ptr(), param_types.len() as u32, 0)
-    };
-    let function = unsafe { core::LLVMAddFunction(module, function_name.as_ptr(), function_type) };
-    let entry_block = unsafe { core::LLVMAppendBasicBlock(function, c_str_ptr!("entry")) };
-    let builder = unsafe {
-        let b = core::LLVMCreateBuilder();
-        core::LLVMPositionBuilderAtEnd(b, entry_block);
-        b
+        core::LLVMFunctionType(core::LLVMVoidTypeInContext(ctx), param_types.as_mut_ptr(), param_types.len() as u32, 0)
     };
+    let function = unsafe { core::LLVMAddFunction(*module, function_name.as_ptr(), function_type) };
+    let entry_block = unsafe { core::LLVMAppendBasicBlockInContext(ctx, function, c_str_ptr!("entry")) };
+    let builder = Builder::at_end(ctx, entry_block);
 
     let mut varmap = HashMap::new();
+    // names of `declare`d functions still waiting for their `fn`
+    let mut declared = HashSet::new();
+    let mut counters = options.profile.then(Vec::new);
+    for (name, value) in &options.defines {
+        compile_define(name, value, module, &builder, &mut varmap).map_err(|e| e.when(&format!("defining {name}={value}")))?;
+    }
+    if options.prelude {
+        compile_prelude(module, &mut varmap);
+    }
     while tokens.this().is_ok() {
         let tok = tokens.this()?;
         match tok.tt {
-            TokenType::Ident(ident) => match ident.as_str() {
-                "const" => compile_global_const(&mut tokens, &module, &builder, &mut varmap),
-                "extern" => compile_extern(&mut tokens, &module, &mut varmap),
-                "fn" => compile_fn(&mut tokens, &module, &mut varmap),
-                e => return Err(ParseET::ParseError("[const|extern|fn]".to_string(), e.to_string()).at(tok.loc))
+            TokenType::Ident(ident) => match &*ident.as_str() {
+                "const" => compile_global_const(&mut tokens, module, &builder, &mut varmap),
+                "global" => compile_global(&mut tokens, module, &builder, &mut varmap),
+                "extern" => compile_extern(&mut tokens, module, &mut varmap),
+                "declare" => {
+                    // a forward declaration is an extern that a later `fn` provides the body for
+                    tokens.next();
+                    compile_extern_fn(&mut tokens, module, &mut varmap).map(|name| { declared.insert(name); })
+                }
+                "fn" => compile_fn(&mut tokens, module, &mut varmap, &mut declared, counters.as_mut()),
+                "assert_size" => compile_assert_size(&mut tokens, module),
+                e => return Err(ParseET::ParseError("[const|global|extern|declare|fn|assert_size]".to_string(), e.to_string()).at(tok.loc))
             }
             e => return Err(ParseET::ParseError("keyword".to_string(), format!("{e:?}")).at(tok.loc))
         }?;
     }
+    if let Some(name) = declared.iter().min_by_key(|name| name.as_str()) {
+        return Err(ParseET::ParseError(format!("fn {name} after its declaration"), "nothing".to_string()).error())
+    }
+
+    if let Some(counters) = counters {
+        compile_profile_dump(module, &builder, counters);
+    }
+    let Some(fun) = varmap.get(&Symbol::intern("main")) else {
+        unsafe { core::LLVMDeleteFunction(function) }
+        return Ok(false)
+    };
+    unsafe {
+        let ret_ty = core::LLVMGetReturnType(fun.0);
+        match core::LLVMGetTypeKind(ret_ty) {
+            LLVMTypeKind::LLVMVoidTypeKind => {
+                core::LLVMBuildCall2(*builder, fun.0, fun.1, [].as_mut_ptr(), 0 as c_uint, c_str_ptr!(""));
+                core::LLVMBuildRetVoid(*builder);
+            }
+            LLVMTypeKind::LLVMIntegerTypeKind => {
+                // the entry has to return the exit code, so the void function built so far only initializes
+                core::LLVMBuildRetVoid(*builder);
+                let init = function;
+                let init_name = "main.init";
+                core::LLVMSetValueName2(init, init_name.as_ptr() as *const c_char, init_name.len());
+                core::LLVMSetLinkage(init, LLVMLinkage::LLVMInternalLinkage);
+                let entry_ty = core::LLVMFunctionType(core::LLVMInt32TypeInContext(ctx), [].as_mut_ptr(), 0, 0);
+                let entry = core::LLVMAddFunction(*module, function_name.as_ptr(), entry_ty);
+                core::LLVMPositionBuilderAtEnd(*builder, core::LLVMAppendBasicBlockInContext(ctx, entry, c_str_ptr!("entry")));
+                core::LLVMBuildCall2(*builder, function_type, init, [].as_mut_ptr(), 0, c_str_ptr!(""));
+                let code = core::LLVMBuildCall2(*builder, fun.0, fun.1, [].as_mut_ptr(), 0, c_str_ptr!(""));
+                let code = core::LLVMBuildIntCast2(*builder, code, core::LLVMInt32TypeInContext(ctx), 1, c_str_ptr!("code"));
+                core::LLVMBuildRet(*builder, code);
+            }
+            _ => return Err(ParseET::ParseError("main to return an integer or nothing".to_string(), ty_to_string(ret_ty)).error())
+        }
+    }
+    Ok(true)
+}
 
+pub(crate) fn compile_to_string(src: &str, name: &str) -> Result<String, ParseError> {
+    let tokens = tokenize(Source::from_string(src.to_string()))?;
+    let module = compile(TokIter::new(tokens), name, &CompileOptions::default())?;
     unsafe {
-        let fun = varmap.get("main").unwrap();
-        core::LLVMBuildCall2(builder, fun.0, fun.1, [].as_mut_ptr(), 0 as c_uint, c_str_ptr!(""));
-        core::LLVMBuildRetVoid(builder);
-        core::LLVMDisposeBuilder(builder)
+        let ir = core::LLVMPrintModuleToString(*module);
+        let r = CStr::from_ptr(ir).to_string_lossy().into_owned();
+        core::LLVMDisposeMessage(ir);
+        Ok(r)
     }
-    Ok(module)
 }
 
-fn get_var(name: &str, loc: Span, varmap: &mut HashMap<String, (LLVMTypeRef, LLVMValueRef, bool)>, local_varmap: &mut HashMap<String, (LLVMTypeRef, LLVMValueRef, bool)>) -> Result<(LLVMTypeRef, LLVMValueRef, bool), ParseError>{
-    local_varmap.get(name).map(|t|Ok(t.clone()))
-        .unwrap_or_else(||varmap.get(name).map(|t|t.clone()).ok_or(ParseET::VariableError(name.to_string()).at(loc)))
+fn get_var(name: Symbol, loc: Span, varmap: &mut VarMap, local_varmap: &mut LocalVarMap) -> Result<Var, ParseError>{
+    local_varmap.get(&name).map(|t|Ok(t.clone()))
+        .unwrap_or_else(||varmap.get(&name).map(|t|t.clone()).ok_or(ParseET::VariableError(name.to_string()).at(loc)))
+}
+
+fn internal_error(builder: &prelude::LLVMBuilderRef, msg: String) -> ParseError {
+    let (fun, block) = unsafe {
+        let block = core::LLVMGetInsertBlock(*builder);
+        if block.is_null() {
+            (String::from("<none>"), String::from("<none>"))
+        } else {
+            let mut len = 0;
+            let fun = core::LLVMGetValueName2(core::LLVMGetBasicBlockParent(block), &mut len);
+            (CStr::from_ptr(fun).to_string_lossy().into_owned(),
+             CStr::from_ptr(core::LLVMGetBasicBlockName(block)).to_string_lossy().into_owned())
+        }
+    };
+    ParseET::Internal(msg).error().when(&format!("building block `{block}` of function `{fun}`"))
 }
 
-fn compile_global_const(tokens: &mut TokIter, module: &prelude::LLVMModuleRef, builder: &prelude::LLVMBuilderRef, varmap: &mut HashMap<String, (LLVMTypeRef, LLVMValueRef, bool)>) -> Result<(), ParseError>{
+fn compile_global_const(tokens: &mut TokIter, module: &prelude::LLVMModuleRef, builder: &prelude::LLVMBuilderRef, varmap: &mut VarMap) -> Result<(), ParseError>{
     expect_ident!(tokens, "const");
-    let ty = ident_next!(tokens, "type");
+    let ty = ty_next(tokens)?;
+    let name_loc = tokens.this()?.loc;
     let name = ident_next!(tokens, "name");
+    check_not_defined(name, name_loc, varmap)?;
     expect_ident!(tokens, "is");
     let tok = tokens.this()?;
+    if matches!(tok.tt, TokenType::Literal(Literal::Number(..) | Literal::Bool(_) | Literal::Char(_)) | TokenType::Particle('-' | '[', _))
+        || matches!(tok.tt, TokenType::Ident(i) if is_const_expression(i)) {
+        // anything but a string is an immutable global that reads load from
+        let llvm_ty = ty_str_to_ty(context(module), &ty.as_str())?;
+        let init = const_initializer(tokens, module, builder, varmap, llvm_ty).map_err(|e| e.when(&format!("compiling const {name}")))?;
+        unsafe {
+            let global = core::LLVMAddGlobal(*module, llvm_ty, c_str_ptr!(name));
+            core::LLVMSetInitializer(global, init);
+            core::LLVMSetGlobalConstant(global, 1);
+            varmap.insert(name, (llvm_ty, global, true, is_unsigned_ty(&ty.as_str()), pointee_of(context(module), &ty.as_str())));
+        }
+        return Ok(())
+    }
     let val = match tok.tt {
         TokenType::Literal(lit) => match lit {
             Literal::String(s) => {
+                tokens.next();
                 Ok(s)
             },
             _ => Err(ParseET::ParseError("string literal [only literal type supported]".to_string(), format!("{lit:?}")).at(tok.loc))
         }
+        TokenType::Ident(i) if i == "concat" => {
+            tokens.next();
+            const_concat(tokens)
+        }
+        TokenType::Ident(i) if i == "env" => {
+            tokens.next();
+            const_env(tokens)
+        }
         tt => Err(ParseET::ParseError("literal".to_string(), format!("{tt:?}")).at(tok.loc))
     }?;
+    // the string ptr variant gives an `i8*` to the first char, the plain variant would be a `[n x i8]*`
+    let p = unsafe {core::LLVMBuildGlobalStringPtr(*builder, c_str_ptr!(val), c_str_ptr!(name))};
+    varmap.insert(name, (unsafe{ core::LLVMPointerType(core::LLVMInt8TypeInContext(context(module)), 0) }, p, false, false, None));
+    Ok(())
+}
+
+fn is_const_expression(ident: Symbol) -> bool {
+    ["literal", "call", "cast", "sizeof"].contains(&&*ident.as_str())
+}
+
+fn const_initializer(tokens: &mut TokIter, module: &prelude::LLVMModuleRef, builder: &prelude::LLVMBuilderRef, varmap: &mut VarMap,
+                     ty: LLVMTypeRef) -> Result<LLVMValueRef, ParseError> {
+    let tok = tokens.this()?;
+    let v = match tok.tt {
+        TokenType::Ident(i) if i == "literal" => {
+            tokens.next();
+            compile_literal(tokens, module, builder, varmap, &mut LocalVarMap::default())?
+        }
+        TokenType::Literal(_) | TokenType::Particle('-', _) => {
+            let (lit, negative, loc) = literal_next(tokens)?;
+            const_literal(ty, lit, negative, loc)?
+        }
+        TokenType::Particle('[', _) => const_array(tokens, module, builder, varmap, ty)?,
+        TokenType::Ident(i) if is_const_expression(i) => {
+            // the builder folds operations on constants, anything it could not fold is only known at runtime
+            let v = compile_expression(tokens, module, builder, varmap, &mut LocalVarMap::default(), "")?;
+            if unsafe { core::LLVMIsConstant(v) } == 0 {
+                return Err(ParseET::ParseError("constant expression".to_string(), "value only known at runtime".to_string()).at(tok.loc))
+            }
+            v
+        }
+        tt => return Err(ParseET::ParseError("literal".to_string(), format!("{tt:?}")).at(tok.loc))
+    };
+    if unsafe { core::LLVMTypeOf(v) } != ty {
+        return Err(ParseET::ParseError(format!("initializer of type {}", ty_to_string(ty)), ty_to_string(unsafe { core::LLVMTypeOf(v) })).at(tok.loc))
+    }
+    Ok(v)
+}
+
+fn const_array(tokens: &mut TokIter, module: &prelude::LLVMModuleRef, builder: &prelude::LLVMBuilderRef, varmap: &mut VarMap,
+               ty: LLVMTypeRef) -> Result<LLVMValueRef, ParseError> {
+    let loc = tokens.this()?.loc;
+    if unsafe { core::LLVMGetTypeKind(ty) } != LLVMTypeKind::LLVMArrayTypeKind {
+        return Err(ParseET::ParseError(format!("initializer of type {}", ty_to_string(ty)), "array literal".to_string()).at(loc))
+    }
+    expect_particle!(tokens, '[');
+    let elem_ty = unsafe { core::LLVMGetElementType(ty) };
+    let mut elements = vec![];
+    loop {
+        elements.push(const_initializer(tokens, module, builder, varmap, elem_ty).map_err(|e| e.when(&format!("compiling element {}", elements.len())))?);
+        let tok = tokens.this()?;
+        match tok.tt {
+            TokenType::Particle(',', _) => tokens.next(),
+            TokenType::Particle(']', _) => { tokens.next(); break }
+            tt => return Err(ParseET::ParseError("[,|]]".to_string(), format!("{tt:?}")).at(tok.loc))
+        }
+    }
+    let len = unsafe { core::LLVMGetArrayLength(ty) } as usize;
+    if elements.len() != len {
+        return Err(ParseET::ParseError(format!("{len} elements"), elements.len().to_string()).at(loc))
+    }
+    Ok(unsafe { core::LLVMConstArray(elem_ty, elements.as_mut_ptr(), len as c_uint) })
+}
+
+fn compile_global(tokens: &mut TokIter, module: &prelude::LLVMModuleRef, builder: &prelude::LLVMBuilderRef, varmap: &mut VarMap) -> Result<(), ParseError> {
+    expect_ident!(tokens, "global");
+    let ty_name = ty_next(tokens)?;
+    let ty = ty_str_to_ty(context(module), &ty_name.as_str())?;
+    let name_loc = tokens.this()?.loc;
+    let name = ident_next!(tokens, "name");
+    check_not_defined(name, name_loc, varmap)?;
+    let init = if tokens.this().map(|t| t.tt == TokenType::Ident(Symbol::intern("is"))).unwrap_or(false) {
+        tokens.next();
+        const_initializer(tokens, module, builder, varmap, ty).map_err(|e| e.when(&format!("compiling global {name}")))?
+    } else {
+        unsafe { core::LLVMConstNull(ty) }
+    };
+    let global = unsafe {
+        let global = core::LLVMAddGlobal(*module, ty, c_str_ptr!(name));
+        core::LLVMSetInitializer(global, init);
+        global
+    };
+    // like a var, the global is a pointer that reads load from and update stores to
+    varmap.insert(name, (ty, global, true, is_unsigned_ty(&ty_name.as_str()), pointee_of(context(module), &ty_name.as_str())));
+    Ok(())
+}
+
+fn const_env(tokens: &mut TokIter) -> Result<String, ParseError> {
+    let string_lit = |tokens: &mut TokIter, expected: &str| {
+        let tok = tokens.this()?;
+        tokens.next();
+        if let TokenType::Literal(Literal::String(s)) = tok.tt {
+            Ok((s, tok.loc))
+        } else {
+            Err(ParseET::ParseError(expected.to_string(), format!("{:?}", tok.tt)).at(tok.loc))
+        }
+    };
+    let (name, loc) = string_lit(tokens, "environment variable name")?;
+    let default = if tokens.this().map_or(false, |t| t.tt == TokenType::Ident(Symbol::intern("or"))) {
+        tokens.next();
+        Some(string_lit(tokens, "default string")?.0)
+    } else { None };
+    match (var(&name), default) {
+        (Ok(value), _) => Ok(value),
+        (Err(_), Some(default)) => Ok(default),
+        (Err(e), None) => Err(ParseET::ParseError(format!("environment variable `{name}`"), e.to_string()).at(loc)
+            .when("reading compile time env"))
+    }
+}
+
+fn compile_assert_size(tokens: &mut TokIter, module: &prelude::LLVMModuleRef) -> Result<(), ParseError> {
+    expect_ident!(tokens, "assert_size");
+    let ty_tok = tokens.this()?;
+    let ty_name = ty_next(tokens)?;
+    let ty = ty_str_to_ty(context(module), &ty_name.as_str()).map_err(|e| e.at(ty_tok.loc.clone()))?;
+    let tok = tokens.this()?;
+    let expected = if let TokenType::Literal(Literal::Number(NumLit::Integer(i), _)) = tok.tt {
+        i
+    } else {
+        return Err(ParseET::ParseError("size in bytes".to_string(), format!("{:?}", tok.tt)).at(tok.loc))
+    };
     tokens.next();
-    let p = unsafe {core::LLVMBuildGlobalString(*builder, c_str_ptr!(val), c_str_ptr!(name))};
-    varmap.insert(name, (unsafe{ core::LLVMPointerType(core::LLVMInt8Type(), 0) }, p, false));
+    if unsafe { core::LLVMTypeIsSized(ty) } == 0 {
+        return Err(ParseET::ParseError("sized type".to_string(), ty_name.to_string()).at(ty_tok.loc).when("asserting type size"))
+    }
+    let actual = unsafe { target::LLVMABISizeOfType(target::LLVMGetModuleDataLayout(*module), ty) };
+    if actual as u128 != expected {
+        return Err(ParseET::ParseError(format!("`{ty_name}` to be {expected} bytes"), format!("{actual} bytes")).at(tok.loc)
+            .when("asserting type size"))
+    }
+    Ok(())
+}
+
+fn const_concat(tokens: &mut TokIter) -> Result<String, ParseError> {
+    let mut s = String::new();
+    for _ in 0..2 {
+        let tok = tokens.this()?;
+        tokens.next();
+        match tok.tt {
+            TokenType::Literal(Literal::String(part)) => s.push_str(&part),
+            TokenType::Ident(i) if i == "concat" => s.push_str(&const_concat(tokens)?),
+            TokenType::Ident(i) if i == "env" => s.push_str(&const_env(tokens)?),
+            tt => return Err(ParseET::ParseError("string literal".to_string(), format!("{tt:?}")).at(tok.loc)
+                .when("folding compile time concat"))
+        }
+    }
+    Ok(s)
+}
+
+fn check_not_defined(name: Symbol, loc: Span, varmap: &VarMap) -> Result<(), ParseError> {
+    if varmap.contains_key(&name) {
+        return Err(ParseET::DuplicateDefinition(name.to_string()).at(loc))
+    }
+    Ok(())
+}
+
+const PRELUDE: [(&str, &str, &[&str]); 4] = [
+    ("malloc", "ptr", &["i64"]),
+    ("free", "void", &["ptr"]),
+    ("memcpy", "ptr", &["ptr", "ptr", "i64"]),
+    ("memset", "ptr", &["ptr", "i32", "i64"]),
+];
+
+fn compile_prelude(module: &prelude::LLVMModuleRef, varmap: &mut VarMap) {
+    for (name, ret, params) in PRELUDE {
+        let mut params: Vec<_> = params.iter().map(|p| ty_str_to_ty(context(module), p).unwrap()).collect();
+        unsafe {
+            let fun_ty = core::LLVMFunctionType(ty_str_to_ty(context(module), ret).unwrap(), params.as_mut_ptr(), params.len() as c_uint, 0);
+            let fun = get_or_add_function(module, name, fun_ty);
+            varmap.insert(Symbol::intern(name), (fun_ty, fun, false, false, None));
+        }
+    }
+}
+
+/// digits that do not make a valid integer of the type are an error rather than a string
+fn compile_define(name: &str, value: &str, module: &prelude::LLVMModuleRef, builder: &prelude::LLVMBuilderRef, varmap: &mut VarMap) -> Result<(), ParseError> {
+    let (negative, digits) = match value.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, value)
+    };
+    let (ty, v, unsigned) = match str_to_num_lit(digits.to_string()) {
+        Ok((NumLit::Integer(i), ty_name)) => {
+            let ty_name = ty_name.map_or(String::from("i32"), |t| t.to_string());
+            let ty = ty_str_to_ty(context(module), &ty_name)?;
+            if unsafe { core::LLVMGetTypeKind(ty) } != LLVMTypeKind::LLVMIntegerTypeKind {
+                return Err(ParseET::ParseError("integer type suffix".to_string(), value.to_string()).error())
+            }
+            let v = const_int(ty, i, negative)
+                .ok_or_else(|| ParseET::ParseError(format!("integer fitting {ty_name}"), value.to_string()).error())?;
+            (ty, v, is_unsigned_ty(&ty_name))
+        }
+        Ok((NumLit::Float(_), _)) => return Err(ParseET::ParseError("integer or string define".to_string(), value.to_string()).error()),
+        Err(e) if digits.starts_with(|c: char| c.is_ascii_digit()) && digits.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') => return Err(e),
+        Err(_) => unsafe {
+            (core::LLVMPointerType(core::LLVMInt8TypeInContext(context(module)), 0), core::LLVMBuildGlobalStringPtr(*builder, c_str_ptr!(value), c_str_ptr!(name)), false)
+        }
+    };
+    varmap.insert(Symbol::intern(name), (ty, v, false, unsigned, None));
     Ok(())
 }
 
-fn fn_sig(tokens: &mut TokIter) -> Result<(String, Option<String>, Vec<(String, String)>, bool), ParseError> {
+fn fn_sig(tokens: &mut TokIter) -> Result<(Symbol, Option<Symbol>, Vec<(Symbol, Symbol)>, bool, Option<String>), ParseError> {
     expect_ident!(tokens, "fn");
+    let section = if let TokenType::Particle('@', _) = tokens.this()?.tt {
+        tokens.next();
+        expect_ident!(tokens, "section");
+        expect_particle!(tokens, '(');
+        let tok = tokens.this()?;
+        let section = if let TokenType::Literal(Literal::String(section)) = tok.tt {
+            section
+        } else {
+            return Err(ParseET::ParseError("section name string".to_string(), format!("{:?}", tok.tt)).at(tok.loc))
+        };
+        tokens.next();
+        expect_particle!(tokens, ')');
+        Some(section)
+    } else { None };
     let name = ident_next!(tokens, "name");
     let n = ident_next!(tokens, "[with|do|end|<type>]");
-    match n.as_str() {
-        "do" | "end"  => Ok((name, None, vec![], false)),
+    match &*n.as_str() {
+        "do" | "end"  => Ok((name, None, vec![], false, section)),
         "with" => {
             let vararg = if &ident_next!(tokens, "<vararg?>") == "vararg" {
                 true
@@ -118,21 +654,22 @@ fn fn_sig(tokens: &mut TokIter) -> Result<(String, Option<String>, Vec<(String,
             };
             let mut args = vec![];
             loop {
-                args.push((ident_next!(tokens, "[with|do|end|<type>]"), ident_next!(tokens, "name")));
+                args.push((ty_next(tokens)?, ident_next!(tokens, "name")));
                 let n = ident_next!(tokens, "[do|end]");
                 if n == "do" || n == "end" {
                     break
                 }
                 tokens.index -= 1
             }
-            Ok((name, None, args, vararg))
+            Ok((name, None, args, vararg, section))
         }
         _  => {
             tokens.index -= 1;
-            let ty = ident_next!(tokens, "<type>");
+            let ty = ty_next(tokens)?;
+            let n2_loc = tokens.this()?.loc;
             let n2 = ident_next!(tokens, "[with|do|end]");
-            match n2.as_str() {
-                "do" | "end"  => Ok((name, Some(ty), vec![], false)),
+            match &*n2.as_str() {
+                "do" | "end"  => Ok((name, Some(ty), vec![], false, section)),
                 "with" => {
                     let vararg = if &ident_next!(tokens, "<vararg?>") == "vararg" {
                         true
@@ -142,129 +679,711 @@ fn fn_sig(tokens: &mut TokIter) -> Result<(String, Option<String>, Vec<(String,
                     };
                     let mut args = vec![];
                     loop {
-                        args.push((ident_next!(tokens, "[with|do|end|<type>]"), ident_next!(tokens, "name")));
+                        args.push((ty_next(tokens)?, ident_next!(tokens, "name")));
                         let n = ident_next!(tokens, "[do|end]");
                         if n == "do" || n == "end" {
                             break
                         }
                         tokens.index -= 1
                     }
-                    Ok((name, Some(ty), args, vararg))
+                    Ok((name, Some(ty), args, vararg, section))
                 }
-                e => Err(ParseET::ParseError("[with|do|end]".to_string(), n2).at(tokens.this()?.loc))
+                _ => Err(ParseET::ParseError("[with|do|end]".to_string(), n2.to_string()).at(n2_loc))
+            }
+        }
+    }
+}
+
+fn ty_next(tokens: &mut TokIter) -> Result<Symbol, ParseError> {
+    let ty = ident_next!(tokens, "type");
+    if ty == "fn" {
+        let mut sig = format!("fn {}", ty_next(tokens)?);
+        if tokens.this()?.tt == TokenType::Ident(Symbol::intern("with")) {
+            tokens.next();
+            sig.push_str(" with");
+            while tokens.this()?.tt != TokenType::Ident(Symbol::intern("end")) {
+                sig.push(' ');
+                sig.push_str(&ty_next(tokens)?.as_str());
             }
         }
+        expect_ident!(tokens, "end");
+        return Ok(Symbol::intern(&format!("{sig} end")))
+    }
+    if ty == "arr" {
+        let elem = ty_next(tokens)?;
+        let tok = tokens.this()?;
+        let len = if let TokenType::Literal(Literal::Number(NumLit::Integer(len), None)) = tok.tt {
+            len
+        } else {
+            return Err(ParseET::ParseError("array length".to_string(), format!("{:?}", tok.tt)).at(tok.loc))
+        };
+        tokens.next();
+        return Ok(Symbol::intern(&format!("arr {elem} {len}")))
+    }
+    if ty != "ptr" {
+        return Ok(ty)
+    }
+    match tokens.this()?.tt {
+        TokenType::Ident(pointee) if is_ty_name(pointee) => Ok(Symbol::intern(&format!("ptr {}", ty_next(tokens)?))),
+        _ => Ok(ty)
+    }
+}
+
+fn is_ty_name(name: Symbol) -> bool {
+    TYPE_NAMES.contains(&&*name.as_str())
+}
+
+fn context(module: &prelude::LLVMModuleRef) -> LLVMContextRef {
+    unsafe { core::LLVMGetModuleContext(*module) }
+}
+
+fn ty_str_to_ty(ctx: LLVMContextRef, ty: &str) -> Result<prelude::LLVMTypeRef, ParseError>{
+    let mut words = ty.split(' ').peekable();
+    let r = words_to_ty(ctx, &mut words)?;
+    match words.next() {
+        Some(word) => Err(ParseET::ParseError("end of type".to_string(), format!("{word} in {ty}")).error()),
+        None => Ok(r)
     }
 }
 
-fn ty_str_to_ty(ty: &str) -> Result<prelude::LLVMTypeRef, ParseError>{
+fn words_to_ty(ctx: LLVMContextRef, words: &mut Peekable<Split<char>>) -> Result<prelude::LLVMTypeRef, ParseError>{
+    let value_ty = |words: &mut Peekable<Split<char>>, what: &str| {
+        let ty = words_to_ty(ctx, words)?;
+        if unsafe { core::LLVMGetTypeKind(ty) } == LLVMTypeKind::LLVMVoidTypeKind {
+            return Err(ParseET::ParseError(what.to_string(), "void".to_string()).error())
+        }
+        Ok(ty)
+    };
+    let ty = words.next().unwrap_or_default();
     unsafe {
         match ty {
-            "void" => Ok(core::LLVMVoidType()),
-            "bool" => Ok(core::LLVMInt1Type()),
-            "ptr" => Ok(core::LLVMPointerType(core::LLVMInt8Type(), 0)),
-            "i8" =>  Ok(core::LLVMInt8Type()),
-            "i32" =>  Ok(core::LLVMInt32Type()),
-            "i64" =>  Ok(core::LLVMInt64Type()),
-            "i128" =>  Ok(core::LLVMInt128Type()),
-            _ => Err(ParseET::ParseError("valid type".to_string(), ty.to_string()).error())
+            "ptr" if words.peek().map_or(false, |w| TYPE_NAMES.contains(w)) => Ok(core::LLVMPointerType(value_ty(words, "pointee type")?, 0)),
+            "arr" => {
+                let elem_ty = value_ty(words, "element type")?;
+                let len = words.next().unwrap_or_default();
+                let len = len.parse().map_err(|_| ParseET::ParseError("array length".to_string(), len.to_string()).error())?;
+                Ok(core::LLVMArrayType(elem_ty, len))
+            }
+            // a function type is not a value, variables hold pointers to functions
+            "fn" => Ok(core::LLVMPointerType(words_to_fn_ty(ctx, words)?, 0)),
+            "void" => Ok(core::LLVMVoidTypeInContext(ctx)),
+            "bool" => Ok(core::LLVMInt1TypeInContext(ctx)),
+            "ptr" => Ok(core::LLVMPointerType(core::LLVMInt8TypeInContext(ctx), 0)),
+            "i8" =>  Ok(core::LLVMInt8TypeInContext(ctx)),
+            "i16" =>  Ok(core::LLVMInt16TypeInContext(ctx)),
+            "i32" =>  Ok(core::LLVMInt32TypeInContext(ctx)),
+            "i64" =>  Ok(core::LLVMInt64TypeInContext(ctx)),
+            "i128" =>  Ok(core::LLVMInt128TypeInContext(ctx)),
+            "u8" =>  Ok(core::LLVMInt8TypeInContext(ctx)),
+            "u16" =>  Ok(core::LLVMInt16TypeInContext(ctx)),
+            "u32" =>  Ok(core::LLVMInt32TypeInContext(ctx)),
+            "u64" =>  Ok(core::LLVMInt64TypeInContext(ctx)),
+            "f32" =>  Ok(core::LLVMFloatTypeInContext(ctx)),
+            "f64" =>  Ok(core::LLVMDoubleTypeInContext(ctx)),
+            _ => Err(ParseET::ParseError("valid type".to_string(), match suggest_ty(ty) {
+                Some(s) => format!("{ty}, did you mean `{s}`?"),
+                None => ty.to_string()
+            }).error())
+        }
+    }
+}
+
+fn words_to_fn_ty(ctx: LLVMContextRef, words: &mut Peekable<Split<char>>) -> Result<prelude::LLVMTypeRef, ParseError>{
+    let ret_ty = words_to_ty(ctx, words)?;
+    let mut params = vec![];
+    if words.peek() == Some(&"with") {
+        words.next();
+        while words.peek().map_or(false, |w| *w != "end") {
+            let ty = words_to_ty(ctx, words)?;
+            if unsafe { core::LLVMGetTypeKind(ty) } == LLVMTypeKind::LLVMVoidTypeKind {
+                return Err(ParseET::ParseError("parameter type".to_string(), "void".to_string()).error())
+            }
+            params.push(ty);
+        }
+    }
+    if words.next() != Some("end") {
+        return Err(ParseET::ParseError("end of function type".to_string(), "nothing".to_string()).error())
+    }
+    Ok(unsafe { core::LLVMFunctionType(ret_ty, params.as_mut_ptr(), params.len() as c_uint, 0) })
+}
+
+/// with opaque pointers the pointee cannot be read back from the llvm type
+fn pointee_of(ctx: LLVMContextRef, ty: &str) -> Option<LLVMTypeRef> {
+    let mut words = ty.split(' ').peekable();
+    match words.next() {
+        Some("fn") => words_to_fn_ty(ctx, &mut words).ok(),
+        Some("ptr") if words.peek().is_some() => words_to_ty(ctx, &mut words).ok(),
+        _ => None
+    }
+}
+
+/// ordered by how common they are, so ties in edit distance resolve to the more likely type
+const TYPE_NAMES: [&str; 16] = ["i32", "ptr", "bool", "void", "i64", "f64", "u8", "u32", "u64", "i8", "f32", "i16", "u16", "i128", "arr", "fn"];
+
+/// llvm integers have no sign, so unsigned types are tracked by name alongside the value
+fn is_unsigned_ty(ty: &str) -> bool {
+    matches!(ty, "u8" | "u16" | "u32" | "u64")
+}
+
+fn suggest_ty(ty: &str) -> Option<&'static str> {
+    TYPE_NAMES.iter()
+        .map(|name| (*name, levenshtein(ty, name)))
+        .filter(|(name, d)| *d <= usize::max(1, name.len() / 2))
+        // the first of several equally close names wins, so ties follow the order of TYPE_NAMES
+        .min_by_key(|(_, d)| *d)
+        .map(|(name, _)| name)
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<char>>();
+    let mut row = (0..=b.len()).collect::<Vec<usize>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diag = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = usize::min(usize::min(row[j], above) + 1, diag + (ca != *cb) as usize);
+            diag = above;
         }
     }
+    row[b.len()]
 }
 
-fn compile_extern(tokens: &mut TokIter, module: &prelude::LLVMModuleRef, varmap: &mut HashMap<String, (LLVMTypeRef, LLVMValueRef, bool)>) -> Result<(), ParseError> {
+fn compile_extern(tokens: &mut TokIter, module: &prelude::LLVMModuleRef, varmap: &mut VarMap) -> Result<(), ParseError> {
     expect_ident!(tokens, "extern");
-    let (name, ty, args, vararg) = fn_sig(tokens)?;
+    if tokens.this()?.tt != TokenType::Ident(Symbol::intern("block")) {
+        return compile_extern_fn(tokens, module, varmap).map(|_| ())
+    }
+    tokens.next();
+    while tokens.this()?.tt != TokenType::Ident(Symbol::intern("end")) {
+        compile_extern_fn(tokens, module, varmap)?;
+    }
+    expect_ident!(tokens, "end");
+    Ok(())
+}
+
+fn compile_extern_fn(tokens: &mut TokIter, module: &prelude::LLVMModuleRef, varmap: &mut VarMap) -> Result<Symbol, ParseError> {
+    let loc = tokens.this()?.loc;
+    let (name, ty, args, vararg, _section) = fn_sig(tokens)?;
+    check_not_defined(name, loc, varmap)?;
     let fn_name = c_str!(name);
-    let ret_ty = ty_str_to_ty(&ty.unwrap_or("void".to_string()))?;
-    let mut params = args.iter().map(|(t, _)| ty_str_to_ty(t.as_str())).collect::<Result<Vec<LLVMTypeRef>, _>>()?;
+    let ret_ty = ty_str_to_ty(context(module), &ty.map_or(Rc::from("void"), |t| t.as_str()))?;
+    let mut params = args.iter().map(|(t, _)| ty_str_to_ty(context(module), &t.as_str())).collect::<Result<Vec<LLVMTypeRef>, _>>()?;
     unsafe {
         let puts_fn_ty = core::LLVMFunctionType(ret_ty, params.as_mut_ptr(), params.len() as c_uint, vararg as LLVMBool);
         let puts_fn = core::LLVMAddFunction(*module, fn_name.as_ptr(), puts_fn_ty.clone());
-        varmap.insert(name, (puts_fn_ty, puts_fn, false));
+        varmap.insert(name, (puts_fn_ty, puts_fn, false, false, None));
     }
-    Ok(())
+    Ok(name)
 }
 
 fn compile_fn(tokens: &mut TokIter, module: &prelude::LLVMModuleRef,
-              varmap: &mut HashMap<String, (LLVMTypeRef, LLVMValueRef, bool)>) -> Result<(), ParseError> {
-    let (name, ty, args, vararg) = fn_sig(tokens)?;
+              varmap: &mut VarMap,
+              declared: &mut HashSet<Symbol>,
+              counters: Option<&mut Vec<(Symbol, LLVMValueRef)>>) -> Result<(), ParseError> {
+    let loc = tokens.this()?.loc;
+    let (name, ty, args, vararg, section) = fn_sig(tokens)?;
     let function_name = c_str!(name.as_str());
     let mut param_names = vec![];
     let mut param_types = vec![];
-    let ret_ty = ty_str_to_ty(&ty.clone().unwrap_or(String::from("void")))?;
+    let mut param_unsigned = vec![];
+    let mut param_pointees = vec![];
+    let ret_ty = ty_str_to_ty(context(module), &ty.map_or(Rc::from("void"), |t| t.as_str()))?;
     let function_type = unsafe {
         for (ty, n) in args {
-            param_types.push(ty_str_to_ty(&ty).unwrap());
+            param_types.push(ty_str_to_ty(context(module), &ty.as_str())?);
+            param_unsigned.push(is_unsigned_ty(&ty.as_str()));
+            param_pointees.push(pointee_of(context(module), &ty.as_str()));
             param_names.push(n);
         }
         core::LLVMFunctionType(ret_ty, param_types.as_mut_ptr(), param_types.len() as u32, vararg as LLVMBool)
     };
-    let function = unsafe { core::LLVMAddFunction(*module, function_name.as_ptr(), function_type) };
-    varmap.insert(name.clone(), (function_type, function, false));
-    let mut local_varmap = HashMap::new();
+    let function = match varmap.get(&name) {
+        // defining a function declared earlier, the body goes into the existing declaration
+        Some((decl_ty, decl, ..)) if declared.contains(&name) => {
+            declared.remove(&name);
+            if *decl_ty != function_type {
+                return Err(ParseET::ParseError(format!("signature `{}` as declared", ty_to_string(*decl_ty)), format!("`{}`", ty_to_string(function_type)))
+                    .at(loc).when(&format!("defining declared function {name}")))
+            }
+            *decl
+        }
+        _ => {
+            check_not_defined(name, loc, varmap)?;
+            unsafe { core::LLVMAddFunction(*module, function_name.as_ptr(), function_type) }
+        }
+    };
+    if let Some(section) = section {
+        unsafe { core::LLVMSetSection(function, c_str_ptr!(section)) }
+    }
+    varmap.insert(name.clone(), (function_type, function, false, false, None));
+    let mut local_varmap = LocalVarMap::default();
+    let mut scope = HashSet::new();
+    let mut loops = vec![];
     for (i, pn) in param_names.into_iter().enumerate() {
         let v = unsafe { core::LLVMGetParam(function, i as c_uint) };
-        local_varmap.insert(pn, (param_types.remove(0), v, false));
+        local_varmap.insert(pn, (param_types.remove(0), v, false, param_unsigned[i], param_pointees[i]));
+        // so a `let` or `var` of the same name warns about shadowing the param
+        scope.insert(pn);
+    }
+    local_varmap.unsigned_params = param_unsigned;
+    let entry_block = unsafe { core::LLVMAppendBasicBlockInContext(context(module), function, c_str_ptr!("entry")) };
+    let builder = Builder::at_end(context(module), entry_block);
+    if let Some(counters) = counters {
+        unsafe {
+            let i64_ty = core::LLVMInt64TypeInContext(context(module));
+            let counter = core::LLVMAddGlobal(*module, i64_ty, c_str_ptr!(format!("{name}.calls")));
+            core::LLVMSetInitializer(counter, core::LLVMConstInt(i64_ty, 0, 0));
+            core::LLVMSetLinkage(counter, LLVMLinkage::LLVMInternalLinkage);
+            let count = core::LLVMBuildLoad2(*builder, i64_ty, counter, c_str_ptr!(""));
+            let count = core::LLVMBuildAdd(*builder, count, core::LLVMConstInt(i64_ty, 1, 0), c_str_ptr!(""));
+            core::LLVMBuildStore(*builder, count, counter);
+            counters.push((name, counter));
+        }
     }
-    let entry_block = unsafe { core::LLVMAppendBasicBlock(function, c_str_ptr!("entry")) };
-    let builder = unsafe {
-        let b = core::LLVMCreateBuilder();
-        core::LLVMPositionBuilderAtEnd(b, entry_block);
-        b
-    };
 
+    if let Some(call_loc) = first_statement_self_call(tokens, name) {
+        ParseET::InfiniteRecursion(name.to_string()).at(call_loc).warn();
+    }
     unsafe {
-        while tokens.this()?.tt != TokenType::Ident(String::from("end")){
-            compile_statement(tokens, module, &builder, &function, varmap, &mut local_varmap)?;
+        while tokens.this()?.tt != TokenType::Ident(Symbol::intern("end")){
+            compile_statement(tokens, module, &builder, &function, varmap, &mut local_varmap, &mut scope, &mut loops)?;
+        }
+        let terminated = !core::LLVMGetBasicBlockTerminator(core::LLVMGetInsertBlock(*builder)).is_null();
+        if ty.is_none() && !terminated {
+            core::LLVMBuildRetVoid(*builder);
         }
-        if let None = ty {
-            core::LLVMBuildRetVoid(builder);
+        if ty.is_some() && !terminated {
+            return Err(ParseET::MissingReturn(name.to_string()).at(tokens.this()?.loc))
         }
-        core::LLVMDisposeBuilder(builder);
     }
     expect_ident!(tokens, "end");
     Ok(())
 }
 
-fn compile_statement(tokens: &mut TokIter, module: &prelude::LLVMModuleRef, builder: &prelude::LLVMBuilderRef, function: &LLVMValueRef,
-                     varmap: &mut HashMap<String, (LLVMTypeRef, LLVMValueRef, bool)>,
-                     local_varmap: &mut HashMap<String, (LLVMTypeRef, LLVMValueRef, bool)>) -> Result<bool, ParseError> {
-    match ident_next!(tokens, "[let|<expr>]").as_str() {
-        "var" => compile_var_create(tokens, module, builder, varmap, local_varmap)?,
-        "update" => compile_var_update(tokens, module, builder, varmap, local_varmap)?,
-        "let" => compile_let_create(tokens, module, builder, varmap, local_varmap)?,
-        "return" => { compile_return(tokens, module, builder, varmap, local_varmap)?; return Ok(true) },
-        "if" => compile_if(tokens, module, builder, function, varmap, local_varmap)?,
-        "while" => compile_while(tokens, module, builder, function, varmap, local_varmap)?,
-        _ => {
-            tokens.index -= 1;
-            compile_expression(tokens, module, builder, varmap, local_varmap, "")?;
+fn compile_profile_dump(module: &prelude::LLVMModuleRef, builder: &prelude::LLVMBuilderRef, counters: Vec<(Symbol, LLVMValueRef)>) {
+    unsafe {
+        let ctx = context(module);
+        let dump_ty = core::LLVMFunctionType(core::LLVMVoidTypeInContext(ctx), [].as_mut_ptr(), 0, 0);
+        let dump = core::LLVMAddFunction(*module, c_str_ptr!("dump_profile"), dump_ty);
+        core::LLVMSetLinkage(dump, LLVMLinkage::LLVMInternalLinkage);
+        let dump_builder = Builder::at_end(ctx, core::LLVMAppendBasicBlockInContext(ctx, dump, c_str_ptr!("entry")));
+        let ptr_ty = core::LLVMPointerType(core::LLVMInt8TypeInContext(ctx), 0);
+        let printf_ty = core::LLVMFunctionType(core::LLVMInt32TypeInContext(ctx), [ptr_ty].as_mut_ptr(), 1, 1);
+        let printf = get_or_add_function(module, "printf", printf_ty);
+        let fmt = core::LLVMBuildGlobalStringPtr(*dump_builder, c_str_ptr!("%s: %lld calls\n"), c_str_ptr!("profile.fmt"));
+        for (name, counter) in counters {
+            let name = core::LLVMBuildGlobalStringPtr(*dump_builder, c_str_ptr!(name), c_str_ptr!(""));
+            let count = core::LLVMBuildLoad2(*dump_builder, core::LLVMInt64TypeInContext(ctx), counter, c_str_ptr!(""));
+            core::LLVMBuildCall2(*dump_builder, printf_ty, printf, [fmt, name, count].as_mut_ptr(), 3, c_str_ptr!(""));
+        }
+        core::LLVMBuildRetVoid(*dump_builder);
+        let atexit_ty = core::LLVMFunctionType(core::LLVMInt32TypeInContext(ctx), [core::LLVMPointerType(dump_ty, 0)].as_mut_ptr(), 1, 0);
+        let atexit = get_or_add_function(module, "atexit", atexit_ty);
+        core::LLVMBuildCall2(*builder, atexit_ty, atexit, [dump].as_mut_ptr(), 1, c_str_ptr!(""));
+    }
+}
+
+fn first_statement_self_call(tokens: &TokIter, name: Symbol) -> Option<Span> {
+    let ident_at = |i: usize| match tokens.get(i).ok()?.tt {
+        TokenType::Ident(ident) => Some(ident),
+        _ => None
+    };
+    let i = tokens.index;
+    // types can span several words, so they are read the way the statement would read them
+    let mut ahead = tokens.clone();
+    ahead.index = i + 1;
+    let expr = match &*ident_at(i)?.as_str() {
+        "call" => i,
+        "return" => i + 1,
+        "let" => {
+            ty_next(&mut ahead).ok()?;
+            ahead.index + 2
+        }
+        "var" => {
+            if ident_at(i + 2) != Some(Symbol::intern("is")) {
+                ty_next(&mut ahead).ok()?;
+            }
+            if ident_at(ahead.index + 1)? != "is" {
+                return None
+            }
+            ahead.index + 2
+        }
+        _ => return None
+    };
+    if ident_at(expr)? == "call" && ident_at(expr + 1)? == name {
+        tokens.get(expr + 1).ok().map(|t| t.loc)
+    } else { None }
+}
+
+fn compile_statement(tokens: &mut TokIter, module: &prelude::LLVMModuleRef, builder: &prelude::LLVMBuilderRef, function: &LLVMValueRef,
+                     varmap: &mut VarMap,
+                     local_varmap: &mut LocalVarMap,
+                     scope: &mut HashSet<Symbol>,
+                     loops: &mut LoopStack) -> Result<bool, ParseError> {
+    let loc = tokens.this()?.loc;
+    if unsafe { !core::LLVMGetBasicBlockTerminator(core::LLVMGetInsertBlock(*builder)).is_null() } {
+        return Err(ParseET::UnreachableCode.at(loc))
+    }
+    match &*ident_next!(tokens, "[let|<expr>]").as_str() {
+        "var" => compile_var_create(tokens, module, builder, varmap, local_varmap, scope)?,
+        "update" => compile_var_update(tokens, module, builder, varmap, local_varmap)?,
+        "let" => compile_let_create(tokens, module, builder, varmap, local_varmap, scope)?,
+        "return" => { compile_return(tokens, module, builder, varmap, local_varmap)?; return Ok(true) },
+        "unreachable" => { unsafe { core::LLVMBuildUnreachable(*builder); } return Ok(true) },
+        "if" => return compile_if(tokens, module, builder, function, varmap, local_varmap, loops),
+        "guard" => compile_guard(tokens, module, builder, function, varmap, local_varmap, loops)?,
+        "while" => compile_while(tokens, module, builder, function, varmap, local_varmap, loops)?,
+        "match" => return compile_match(tokens, module, builder, function, varmap, local_varmap, loops),
+        "switch" => return compile_switch(tokens, module, builder, function, varmap, local_varmap, loops),
+        "repeat" if tokens.this()?.tt == TokenType::Ident(Symbol::intern("do")) => compile_repeat_until(tokens, module, builder, function, varmap, local_varmap, loops)?,
+        "repeat" => compile_repeat(tokens, module, builder, function, varmap, local_varmap, loops)?,
+        "for" => compile_for(tokens, module, builder, function, varmap, local_varmap, loops)?,
+        "loop" => return compile_loop(tokens, module, builder, function, varmap, local_varmap, loops),
+        "break" => {
+            let (_, break_block) = loops.last()
+                .ok_or_else(|| ParseET::ParseError("break inside a loop".to_string(), "break outside of any loop".to_string()).at(loc))?;
+            unsafe { core::LLVMBuildBr(*builder, *break_block); }
+            return Ok(true)
+        },
+        "continue" => {
+            let (continue_block, _) = loops.last()
+                .ok_or_else(|| ParseET::ParseError("continue inside a loop".to_string(), "continue outside of any loop".to_string()).at(loc))?;
+            unsafe { core::LLVMBuildBr(*builder, *continue_block); }
+            return Ok(true)
+        },
+        _ => {
+            tokens.index -= 1;
+            let v = compile_expression(tokens, module, builder, varmap, local_varmap, "")?;
+            // calls may be made for their side effects, anything else only computes a value
+            unsafe {
+                if core::LLVMIsACallInst(v).is_null() && core::LLVMGetTypeKind(core::LLVMTypeOf(v)) != LLVMTypeKind::LLVMVoidTypeKind {
+                    ParseET::UnusedResult.at(loc).warn();
+                }
+            }
         }
     }
     return Ok(false)
 }
 
 fn compile_expression(tokens: &mut TokIter, module: &prelude::LLVMModuleRef, builder: &prelude::LLVMBuilderRef,
-                     varmap: &mut HashMap<String, (LLVMTypeRef, LLVMValueRef, bool)>,
-                     local_varmap: &mut HashMap<String, (LLVMTypeRef, LLVMValueRef, bool)>,
+                     varmap: &mut VarMap,
+                     local_varmap: &mut LocalVarMap,
                      ret_name: &str) -> Result<LLVMValueRef, ParseError> {
-    let r = match ident_next!(tokens, "[call|literal|<variable>]").as_str() {
-        "call" => compile_fn_call(tokens, module, builder, varmap, local_varmap, ret_name)?,
+    let loc = tokens.this()?.loc;
+    let ident = ident_next!(tokens, "[call|literal|<variable>]");
+    let r = match &*ident.as_str() {
+        "call" => compile_fn_call(tokens, module, builder, varmap, local_varmap, ret_name)?.0,
         "literal" => compile_literal(tokens, module, builder, varmap, local_varmap)?,
-        v => {
-            let (ty, v, is_alloca) = get_var(v, tokens.this()?.loc, varmap, local_varmap)?;
+        "arg" => {
+            let tok = tokens.this()?;
+            let index = if let TokenType::Literal(Literal::Number(NumLit::Integer(i), _)) = tok.tt {
+                i
+            } else {
+                return Err(ParseET::ParseError("parameter index".to_string(), format!("{:?}", tok.tt)).at(tok.loc))
+            };
+            tokens.next();
+            unsafe {
+                let function = core::LLVMGetBasicBlockParent(core::LLVMGetInsertBlock(*builder));
+                let count = core::LLVMCountParams(function);
+                if index >= count as u128 {
+                    return Err(ParseET::ParseError(format!("parameter index below {count}"), index.to_string()).at(tok.loc))
+                }
+                core::LLVMGetParam(function, index as c_uint)
+            }
+        }
+        "abs" => {
+            let loc = tokens.this()?.loc;
+            let v = compile_expression(tokens, module, builder, varmap, local_varmap, "")?;
+            unsafe {
+                let ty = core::LLVMTypeOf(v);
+                match core::LLVMGetTypeKind(ty) {
+                    LLVMTypeKind::LLVMIntegerTypeKind => build_intrinsic_call(module, builder, "llvm.abs", ty,
+                        &mut [v, core::LLVMConstInt(core::LLVMInt1TypeInContext(context(module)), 0, 0)], ret_name),
+                    LLVMTypeKind::LLVMFloatTypeKind | LLVMTypeKind::LLVMDoubleTypeKind => build_intrinsic_call(module, builder, "llvm.fabs", ty,
+                        &mut [v], ret_name),
+                    _ => return Err(ParseET::ParseError("integer or float operand".to_string(), ty_to_string(ty)).at(loc).when("compiling abs"))
+                }
+            }
+        }
+        "env" => {
+            let s = const_env(tokens)?;
+            unsafe { core::LLVMBuildGlobalStringPtr(*builder, c_str_ptr!(s), c_str_ptr!(ret_name)) }
+        }
+        "concat" => {
+            let start = tokens.index;
+            match const_concat(tokens) {
+                Ok(s) => unsafe { core::LLVMBuildGlobalStringPtr(*builder, c_str_ptr!(s), c_str_ptr!(ret_name)) },
+                Err(_) => {
+                    tokens.index = start;
+                    compile_runtime_concat(tokens, module, builder, varmap, local_varmap, ret_name)?
+                }
+            }
+        }
+        "cast" => compile_cast(tokens, module, builder, varmap, local_varmap, ret_name)?,
+        "deref" => {
+            // `deref <type> <expr>` reinterprets the pointer, `deref <var>` loads the pointee of a `ptr <type>` variable
+            let loc = tokens.this()?.loc;
+            let ty = match tokens.this()?.tt {
+                TokenType::Ident(t) if is_ty_name(t) => ty_str_to_ty(context(module), &ty_next(tokens)?.as_str())?,
+                TokenType::Ident(name) => match get_var(name, loc.clone(), varmap, local_varmap)?.4
+                    .filter(|t| unsafe { core::LLVMGetTypeKind(*t) } != LLVMTypeKind::LLVMFunctionTypeKind) {
+                    Some(pointee) => pointee,
+                    None => return Err(ParseET::ParseError("type to load, as in `deref <type> <expr>`".to_string(), format!("`{name}` without a pointee type"))
+                        .at(loc).when("compiling deref"))
+                },
+                tt => return Err(ParseET::ParseError("type to load, as in `deref <type> <expr>`".to_string(), format!("{tt:?}")).at(loc).when("compiling deref"))
+            };
+            let loc = tokens.this()?.loc;
+            let p = compile_expression(tokens, module, builder, varmap, local_varmap, "")?;
+            unsafe {
+                if core::LLVMGetTypeKind(core::LLVMTypeOf(p)) != LLVMTypeKind::LLVMPointerTypeKind {
+                    return Err(ParseET::ParseError("pointer".to_string(), ty_to_string(core::LLVMTypeOf(p))).at(loc).when("compiling deref"))
+                }
+                let p = core::LLVMBuildPointerCast(*builder, p, core::LLVMPointerType(ty, 0), c_str_ptr!(""));
+                core::LLVMBuildLoad2(*builder, ty, p, c_str_ptr!(ret_name))
+            }
+        }
+        "addr" => {
+            let tok = tokens.this()?;
+            let name = ident_next!(tokens, "variable");
+            let (_, v, is_alloca, _, _) = get_var(name, tok.loc.clone(), varmap, local_varmap)?;
+            if !is_alloca && unsafe { core::LLVMIsAFunction(v) }.is_null() {
+                return Err(ParseET::ParseError("variable declared with var or function".to_string(), format!("`{name}`")).at(tok.loc).when("taking address"))
+            }
+            // the alloca already is a `ptr <type>` of the variable, and a function a `fn` pointer to itself
+            v
+        }
+        // an i64 constant expression, llvm folds it once the data layout is known
+        "sizeof" => unsafe { core::LLVMSizeOf(ty_str_to_ty(context(module), &ty_next(tokens)?.as_str())?) }
+        // a variable called `len` is read like any other
+        "len" if get_var(ident, loc.clone(), varmap, local_varmap).is_err() => unsafe {
+            let (_, ty, _) = array_var(tokens, varmap, local_varmap, "taking len")?;
+            core::LLVMConstInt(core::LLVMInt64TypeInContext(context(module)), core::LLVMGetArrayLength(ty) as c_ulonglong, 0)
+        }
+        "index" => unsafe {
+            let (elem_ty, p) = compile_index_ptr(tokens, module, builder, varmap, local_varmap)?;
+            core::LLVMBuildLoad2(*builder, elem_ty, p, c_str_ptr!(ret_name))
+        }
+        op @ ("sqrt" | "sin" | "cos") => {
+            let loc = tokens.this()?.loc;
+            let v = compile_expression(tokens, module, builder, varmap, local_varmap, "")?;
+            unsafe {
+                let ty = core::LLVMTypeOf(v);
+                match core::LLVMGetTypeKind(ty) {
+                    LLVMTypeKind::LLVMFloatTypeKind | LLVMTypeKind::LLVMDoubleTypeKind => build_intrinsic_call(module, builder, &format!("llvm.{op}"), ty,
+                        &mut [v], ret_name),
+                    _ => return Err(ParseET::ParseError("float operand".to_string(), ty_to_string(ty)).at(loc).when(&format!("compiling {op}")))
+                }
+            }
+        }
+        icmp if icmp_predicate(icmp).is_some() => {
+            let a = compile_expression(tokens, module, builder, varmap, local_varmap, "")?;
+            let b = compile_expression(tokens, module, builder, varmap, local_varmap, "")?;
+            unsafe { core::LLVMBuildICmp(*builder, icmp_predicate(icmp).unwrap(), a, b, c_str_ptr!(ret_name)) }
+        }
+        _ => {
+            let (ty, v, is_alloca, _, _) = get_var(ident, tokens.this()?.loc, varmap, local_varmap)?;
             if is_alloca {
                 unsafe { core::LLVMBuildLoad2(*builder, ty, v, c_str_ptr!("")) }
             } else { v }
         }
     };
+    if r.is_null() {
+        return Err(internal_error(builder, format!("llvm built no value for `{ident}`")).at(loc))
+    }
     Ok(r)
 }
 
+fn compile_operand(tokens: &mut TokIter, module: &prelude::LLVMModuleRef, builder: &prelude::LLVMBuilderRef,
+                   varmap: &mut VarMap,
+                   local_varmap: &mut LocalVarMap,
+                   ret_name: &str) -> Result<(LLVMValueRef, bool), ParseError> {
+    let unsigned = match tokens.this()?.tt {
+        TokenType::Ident(i) if i == "call" => {
+            tokens.next();
+            return compile_fn_call(tokens, module, builder, varmap, local_varmap, ret_name)
+        }
+        TokenType::Ident(i) if i == "arg" => match tokens.get(tokens.index + 1)?.tt {
+            TokenType::Literal(Literal::Number(NumLit::Integer(index), _)) => local_varmap.unsigned_params.get(index as usize).copied().unwrap_or(false),
+            _ => false
+        },
+        TokenType::Ident(i) if i == "literal" || i == "cast" => matches!(tokens.get(tokens.index + 1)?.tt, TokenType::Ident(ty) if is_unsigned_ty(&ty.as_str())),
+        TokenType::Ident(i) => local_varmap.get(&i).or_else(|| varmap.get(&i)).map_or(false, |v| v.3),
+        _ => false
+    };
+    Ok((compile_expression(tokens, module, builder, varmap, local_varmap, ret_name)?, unsigned))
+}
+
+/// a negative constant index counts from the end, so `-1` is the last element
+fn compile_index_ptr(tokens: &mut TokIter, module: &prelude::LLVMModuleRef, builder: &prelude::LLVMBuilderRef,
+                     varmap: &mut VarMap,
+                     local_varmap: &mut LocalVarMap) -> Result<(LLVMTypeRef, LLVMValueRef), ParseError> {
+    let (name, ty, v) = array_var(tokens, varmap, local_varmap, "indexing")?;
+    unsafe {
+        let len = core::LLVMGetArrayLength(ty) as i64;
+        let loc = tokens.this()?.loc;
+        let i64_ty = core::LLVMInt64TypeInContext(context(module));
+        let (i, unsigned) = if matches!(tokens.this()?.tt, TokenType::Literal(_) | TokenType::Particle('-', _)) {
+            let (value, negative, loc) = literal_next(tokens)?;
+            (const_literal(i64_ty, value, negative, loc)?, false)
+        } else {
+            compile_operand(tokens, module, builder, varmap, local_varmap, "")?
+        };
+        if core::LLVMGetTypeKind(core::LLVMTypeOf(i)) != LLVMTypeKind::LLVMIntegerTypeKind {
+            return Err(ParseET::ParseError("integer index".to_string(), ty_to_string(core::LLVMTypeOf(i))).at(loc).when(&format!("indexing `{name}`")))
+        }
+        let i = if !core::LLVMIsAConstantInt(i).is_null() {
+            let value = if unsigned { core::LLVMConstIntGetZExtValue(i) as i64 } else { core::LLVMConstIntGetSExtValue(i) };
+            let from_start = if value < 0 { value + len } else { value };
+            if from_start < 0 || from_start >= len {
+                return Err(ParseET::ParseError(format!("index from -{len} to {}", len - 1), value.to_string()).at(loc).when(&format!("indexing `{name}`")))
+            }
+            core::LLVMConstInt(i64_ty, from_start as c_ulonglong, 0)
+        } else if unsigned {
+            // gep indices are signed
+            core::LLVMBuildZExt(*builder, i, i64_ty, c_str_ptr!(""))
+        } else { i };
+        let mut indices = [core::LLVMConstInt(core::LLVMInt32TypeInContext(context(module)), 0, 0), i];
+        let p = core::LLVMBuildGEP2(*builder, ty, v, indices.as_mut_ptr(), 2, c_str_ptr!(""));
+        Ok((core::LLVMGetElementType(ty), p))
+    }
+}
+
+fn array_var(tokens: &mut TokIter, varmap: &mut VarMap, local_varmap: &mut LocalVarMap, doing: &str) -> Result<(Symbol, LLVMTypeRef, LLVMValueRef), ParseError> {
+    let tok = tokens.this()?;
+    let name = ident_next!(tokens, "array variable");
+    let (ty, v, is_alloca, _, _) = get_var(name, tok.loc.clone(), varmap, local_varmap)?;
+    if !is_alloca || unsafe { core::LLVMGetTypeKind(ty) } != LLVMTypeKind::LLVMArrayTypeKind {
+        return Err(ParseET::ParseError("array variable declared with var".to_string(), format!("`{name}`")).at(tok.loc).when(doing))
+    }
+    Ok((name, ty, v))
+}
+
+fn compile_cast(tokens: &mut TokIter, module: &prelude::LLVMModuleRef, builder: &prelude::LLVMBuilderRef,
+                varmap: &mut VarMap,
+                local_varmap: &mut LocalVarMap,
+                ret_name: &str) -> Result<LLVMValueRef, ParseError> {
+    let loc = tokens.this()?.loc;
+    let to_name = ident_next!(tokens, "type");
+    let to = ty_str_to_ty(context(module), &to_name.as_str())?;
+    expect_ident!(tokens, "from");
+    let (v, unsigned) = compile_operand(tokens, module, builder, varmap, local_varmap, "")?;
+    unsafe {
+        let from = core::LLVMTypeOf(v);
+        let is_int = |ty| core::LLVMGetTypeKind(ty) == LLVMTypeKind::LLVMIntegerTypeKind;
+        let name = c_str_ptr!(ret_name);
+        Ok(match (is_int(from), is_float_ty(from), is_int(to), is_float_ty(to)) {
+            (true, _, true, _) => match core::LLVMGetIntTypeWidth(from).cmp(&core::LLVMGetIntTypeWidth(to)) {
+                Ordering::Greater => core::LLVMBuildTrunc(*builder, v, to, name),
+                Ordering::Less if unsigned || core::LLVMGetIntTypeWidth(from) == 1 => core::LLVMBuildZExt(*builder, v, to, name),
+                Ordering::Less => core::LLVMBuildSExt(*builder, v, to, name),
+                Ordering::Equal => v
+            },
+            (true, _, _, true) if unsigned || core::LLVMGetIntTypeWidth(from) == 1 => core::LLVMBuildUIToFP(*builder, v, to, name),
+            (true, _, _, true) => core::LLVMBuildSIToFP(*builder, v, to, name),
+            (_, true, true, _) if is_unsigned_ty(&to_name.as_str()) => core::LLVMBuildFPToUI(*builder, v, to, name),
+            (_, true, true, _) => core::LLVMBuildFPToSI(*builder, v, to, name),
+            (_, true, _, true) => core::LLVMBuildFPCast(*builder, v, to, name),
+            _ => return Err(ParseET::ParseError("integer or float types".to_string(), format!("{} to {}", ty_to_string(from), ty_to_string(to))).at(loc)
+                .when("compiling cast"))
+        })
+    }
+}
+
+/// the result is `malloc`ed and owned by the caller
+fn compile_runtime_concat(tokens: &mut TokIter, module: &prelude::LLVMModuleRef, builder: &prelude::LLVMBuilderRef,
+                          varmap: &mut VarMap,
+                          local_varmap: &mut LocalVarMap,
+                          ret_name: &str) -> Result<LLVMValueRef, ParseError> {
+    let mut operands = [std::ptr::null_mut(); 2];
+    for operand in operands.iter_mut() {
+        let tok = tokens.this()?;
+        *operand = if let TokenType::Literal(Literal::String(s)) = tok.tt {
+            tokens.next();
+            unsafe { core::LLVMBuildGlobalStringPtr(*builder, c_str_ptr!(s), c_str_ptr!("")) }
+        } else {
+            compile_expression(tokens, module, builder, varmap, local_varmap, "")?
+        };
+        let ty = unsafe { core::LLVMTypeOf(*operand) };
+        if unsafe { core::LLVMGetTypeKind(ty) } != LLVMTypeKind::LLVMPointerTypeKind {
+            return Err(ParseET::ParseError("ptr operand".to_string(), ty_to_string(ty)).at(tok.loc).when("compiling concat"))
+        }
+    }
+    let [a, b] = operands;
+    unsafe {
+        let ptr_ty = core::LLVMPointerType(core::LLVMInt8TypeInContext(context(module)), 0);
+        let size_ty = core::LLVMInt64TypeInContext(context(module));
+        let len_a = build_libc_call(module, builder, "strlen", size_ty, &mut [a], "");
+        let len_b = build_libc_call(module, builder, "strlen", size_ty, &mut [b], "");
+        let len = core::LLVMBuildAdd(*builder, len_a, len_b, c_str_ptr!(""));
+        // room for the nul terminator
+        let size = core::LLVMBuildAdd(*builder, len, core::LLVMConstInt(size_ty, 1, 0), c_str_ptr!(""));
+        let buf = build_libc_call(module, builder, "malloc", ptr_ty, &mut [size], ret_name);
+        build_libc_call(module, builder, "strcpy", ptr_ty, &mut [buf, a], "");
+        build_libc_call(module, builder, "strcat", ptr_ty, &mut [buf, b], "");
+        Ok(buf)
+    }
+}
+
+fn build_libc_call(module: &prelude::LLVMModuleRef, builder: &prelude::LLVMBuilderRef, name: &str, ret: LLVMTypeRef,
+                   args: &mut [LLVMValueRef], ret_name: &str) -> LLVMValueRef {
+    unsafe {
+        let mut params: Vec<_> = args.iter().map(|a| core::LLVMTypeOf(*a)).collect();
+        let fun_ty = core::LLVMFunctionType(ret, params.as_mut_ptr(), params.len() as c_uint, 0);
+        let fun = get_or_add_function(module, name, fun_ty);
+        core::LLVMBuildCall2(*builder, fun_ty, fun, args.as_mut_ptr(), args.len() as c_uint, c_str_ptr!(ret_name))
+    }
+}
+
+fn get_or_add_function(module: &prelude::LLVMModuleRef, name: &str, ty: LLVMTypeRef) -> LLVMValueRef {
+    unsafe {
+        let fun = core::LLVMGetNamedFunction(*module, c_str_ptr!(name));
+        if fun.is_null() {
+            core::LLVMAddFunction(*module, c_str_ptr!(name), ty)
+        } else { fun }
+    }
+}
+
+fn build_intrinsic_call(module: &prelude::LLVMModuleRef, builder: &prelude::LLVMBuilderRef, name: &str, ty: LLVMTypeRef,
+                        args: &mut [LLVMValueRef], ret_name: &str) -> LLVMValueRef {
+    unsafe {
+        let id = core::LLVMLookupIntrinsicID(name.as_ptr() as *const c_char, name.len());
+        let mut overloads = [ty];
+        let fun = core::LLVMGetIntrinsicDeclaration(*module, id, overloads.as_mut_ptr(), overloads.len());
+        let fun_ty = core::LLVMIntrinsicGetType(context(module), id, overloads.as_mut_ptr(), overloads.len());
+        core::LLVMBuildCall2(*builder, fun_ty, fun, args.as_mut_ptr(), args.len() as c_uint, c_str_ptr!(ret_name))
+    }
+}
+
+fn ty_to_string(ty: LLVMTypeRef) -> String {
+    unsafe {
+        let s = core::LLVMPrintTypeToString(ty);
+        let r = CStr::from_ptr(s).to_string_lossy().into_owned();
+        core::LLVMDisposeMessage(s);
+        r
+    }
+}
+
+fn icmp_predicate(name: &str) -> Option<LLVMIntPredicate> {
+    match name {
+        "icmp_eq" => Some(LLVMIntPredicate::LLVMIntEQ),
+        "icmp_ne" => Some(LLVMIntPredicate::LLVMIntNE),
+        "icmp_ugt" => Some(LLVMIntPredicate::LLVMIntUGT),
+        "icmp_uge" => Some(LLVMIntPredicate::LLVMIntUGE),
+        "icmp_ult" => Some(LLVMIntPredicate::LLVMIntULT),
+        "icmp_ule" => Some(LLVMIntPredicate::LLVMIntULE),
+        "icmp_sgt" => Some(LLVMIntPredicate::LLVMIntSGT),
+        "icmp_sge" => Some(LLVMIntPredicate::LLVMIntSGE),
+        "icmp_slt" => Some(LLVMIntPredicate::LLVMIntSLT),
+        "icmp_sle" => Some(LLVMIntPredicate::LLVMIntSLE),
+        _ => None
+    }
+}
+
 fn compile_return(tokens: &mut TokIter, module: &prelude::LLVMModuleRef, builder: &prelude::LLVMBuilderRef,
-                    varmap: &mut HashMap<String, (LLVMTypeRef, LLVMValueRef, bool)>,
-                    local_varmap: &mut HashMap<String, (LLVMTypeRef, LLVMValueRef, bool)>) -> Result<(), ParseError> {
+                    varmap: &mut VarMap,
+                    local_varmap: &mut LocalVarMap) -> Result<(), ParseError> {
     unsafe {
         if &ident_next!(tokens, "[end|<var>]") == "end" {
             core::LLVMBuildRetVoid(*builder);
@@ -278,78 +1397,384 @@ fn compile_return(tokens: &mut TokIter, module: &prelude::LLVMModuleRef, builder
 }
 
 fn compile_while(tokens: &mut TokIter, module: &prelude::LLVMModuleRef, builder: &prelude::LLVMBuilderRef, function: &LLVMValueRef,
-              varmap: &mut HashMap<String, (LLVMTypeRef, LLVMValueRef, bool)>,
-              local_varmap: &mut HashMap<String, (LLVMTypeRef, LLVMValueRef, bool)>) -> Result<(), ParseError> {
-    let cond_block = unsafe { core::LLVMAppendBasicBlock(*function, c_str_ptr!("cond")) };
-    let body_block = unsafe { core::LLVMAppendBasicBlock(*function, c_str_ptr!("body")) };
-    let continue_block = unsafe { core::LLVMAppendBasicBlock(*function, c_str_ptr!("whilecont")) };
+              varmap: &mut VarMap,
+              local_varmap: &mut LocalVarMap,
+              loops: &mut LoopStack) -> Result<(), ParseError> {
+    let unroll = match (tokens.this()?.tt, tokens.get(tokens.index + 1).map(|t| t.tt)) {
+        (TokenType::Ident(i), Ok(TokenType::Literal(Literal::Number(NumLit::Integer(n), _)))) if i == "unroll" => {
+            tokens.index += 2;
+            Some(n)
+        }
+        _ => None
+    };
+    let id = next_block_id(function);
+    let cond_block = append_block(function, "cond", id);
+    let body_block = append_block(function, "body", id);
+    let continue_block = append_block(function, "whilecont", id);
     unsafe {
         core::LLVMBuildBr(*builder, cond_block);
         core::LLVMPositionBuilderAtEnd(*builder, cond_block); // START COND
     }
-    let cond_val = compile_expression(tokens, module, builder, varmap, local_varmap, "")?;
+    let cond_val = compile_condition(tokens, module, builder, varmap, local_varmap)?;
+    expect_ident!(tokens, "do");
+    unsafe {
+        core::LLVMBuildCondBr(*builder, cond_val, body_block, continue_block); // END COND
+        core::LLVMPositionBuilderAtEnd(*builder, body_block); // START BODY
+    }
+    loops.push((cond_block, continue_block));
+    local_varmap.push_scope();
+    let mut body_scope = HashSet::new();
+    let mut does_return = false;
+    while {
+        let n = ident_next!(tokens, "end");
+        tokens.index -= 1;
+        &n != "end"
+    } {
+        if compile_statement(tokens, module, builder, function, varmap, local_varmap, &mut body_scope, loops)? {
+            does_return = true;
+        }
+    }
+    expect_ident!(tokens, "end");
+    loops.pop();
+    local_varmap.pop_scope();
+
+    unsafe {
+        if !does_return {
+            let back_edge = core::LLVMBuildBr(*builder, cond_block); // END BODY
+            if let Some(n) = unroll {
+                set_loop_metadata(back_edge, "llvm.loop.unroll.count", n as c_ulonglong);
+            }
+        }
+        core::LLVMPositionBuilderAtEnd(*builder, continue_block); // CONTINUE
+    }
+    Ok(())
+}
+
+/// integers are implicitly true when not zero
+fn compile_condition(tokens: &mut TokIter, module: &prelude::LLVMModuleRef, builder: &prelude::LLVMBuilderRef,
+                     varmap: &mut VarMap,
+                     local_varmap: &mut LocalVarMap) -> Result<LLVMValueRef, ParseError> {
+    let loc = tokens.this()?.loc;
+    let v = compile_expression(tokens, module, builder, varmap, local_varmap, "")?;
+    unsafe {
+        let ty = core::LLVMTypeOf(v);
+        if core::LLVMGetTypeKind(ty) != LLVMTypeKind::LLVMIntegerTypeKind {
+            return Err(ParseET::ParseError("bool or integer condition".to_string(), ty_to_string(ty)).at(loc))
+        }
+        if core::LLVMGetIntTypeWidth(ty) == 1 {
+            return Ok(v)
+        }
+        Ok(core::LLVMBuildICmp(*builder, LLVMIntPredicate::LLVMIntNE, v, core::LLVMConstNull(ty), c_str_ptr!("")))
+    }
+}
+
+fn set_loop_metadata(back_edge: LLVMValueRef, hint: &str, value: c_ulonglong) {
+    unsafe {
+        let ctx = core::LLVMGetTypeContext(core::LLVMTypeOf(back_edge));
+        let hint = [
+            core::LLVMMDStringInContext2(ctx, hint.as_ptr() as *const c_char, hint.len()),
+            core::LLVMValueAsMetadata(core::LLVMConstInt(core::LLVMInt32TypeInContext(ctx), value, 0))
+        ];
+        let hint = core::LLVMMDNodeInContext2(ctx, hint.as_ptr() as *mut _, hint.len());
+        // a loop id has to refer to itself, so it starts out with a placeholder that gets replaced
+        let placeholder = debuginfo::LLVMTemporaryMDNode(ctx, std::ptr::null_mut(), 0);
+        let mut operands = [placeholder, hint];
+        let loop_id = core::LLVMMDNodeInContext2(ctx, operands.as_mut_ptr(), operands.len());
+        debuginfo::LLVMMetadataReplaceAllUsesWith(placeholder, loop_id);
+        let kind = core::LLVMGetMDKindID("llvm.loop".as_ptr() as *const c_char, "llvm.loop".len() as c_uint);
+        core::LLVMSetMetadata(back_edge, kind, core::LLVMMetadataAsValue(ctx, loop_id));
+    }
+}
+
+fn next_block_id(function: &LLVMValueRef) -> c_uint {
+    unsafe { core::LLVMCountBasicBlocks(*function) }
+}
+
+fn append_block(function: &LLVMValueRef, name: &str, id: c_uint) -> LLVMBasicBlockRef {
+    unsafe {
+        let ctx = core::LLVMGetTypeContext(core::LLVMTypeOf(*function));
+        core::LLVMAppendBasicBlockInContext(ctx, *function, c_str_ptr!(format!("{name}.{id}")))
+    }
+}
+
+fn compile_loop(tokens: &mut TokIter, module: &prelude::LLVMModuleRef, builder: &prelude::LLVMBuilderRef, function: &LLVMValueRef,
+                varmap: &mut VarMap,
+                local_varmap: &mut LocalVarMap,
+                loops: &mut LoopStack) -> Result<bool, ParseError> {
+    let id = next_block_id(function);
+    let body_block = append_block(function, "loop", id);
+    let continue_block = append_block(function, "loopcont", id);
+    unsafe {
+        core::LLVMBuildBr(*builder, body_block);
+        core::LLVMPositionBuilderAtEnd(*builder, body_block); // START BODY
+    }
+    loops.push((body_block, continue_block));
+    local_varmap.push_scope();
+    let mut body_scope = HashSet::new();
+    let mut does_return = false;
+    while {
+        let n = ident_next!(tokens, "end");
+        tokens.index -= 1;
+        &n != "end"
+    } {
+        if compile_statement(tokens, module, builder, function, varmap, local_varmap, &mut body_scope, loops)? {
+            does_return = true;
+        }
+    }
+    expect_ident!(tokens, "end");
+    loops.pop();
+    local_varmap.pop_scope();
+
+    unsafe {
+        if !does_return {
+            core::LLVMBuildBr(*builder, body_block); // END BODY
+        }
+        core::LLVMPositionBuilderAtEnd(*builder, continue_block); // CONTINUE
+        // only a break reaches the continue block, without one the loop never falls through
+        let diverges = core::LLVMGetFirstUse(core::LLVMBasicBlockAsValue(continue_block)).is_null();
+        if diverges {
+            core::LLVMBuildUnreachable(*builder);
+        }
+        Ok(diverges)
+    }
+}
+
+fn compile_repeat(tokens: &mut TokIter, module: &prelude::LLVMModuleRef, builder: &prelude::LLVMBuilderRef, function: &LLVMValueRef,
+                  varmap: &mut VarMap,
+                  local_varmap: &mut LocalVarMap,
+                  loops: &mut LoopStack) -> Result<(), ParseError> {
+    let loc = tokens.this()?.loc;
+    let (count, unsigned) = compile_operand(tokens, module, builder, varmap, local_varmap, "")?;
+    let ty = unsafe { core::LLVMTypeOf(count) };
+    if unsafe { core::LLVMGetTypeKind(ty) } != LLVMTypeKind::LLVMIntegerTypeKind {
+        return Err(ParseET::ParseError("integer count".to_string(), ty_to_string(ty)).at(loc).when("compiling repeat"))
+    }
     expect_ident!(tokens, "do");
+    let counter = entry_alloca(function, ty, "repeati");
+    unsafe { core::LLVMBuildStore(*builder, core::LLVMConstInt(ty, 0, 0), counter); }
+    let id = next_block_id(function);
+    let cond_block = append_block(function, "cond", id);
+    let body_block = append_block(function, "body", id);
+    let step_block = append_block(function, "step", id);
+    let continue_block = append_block(function, "repeatcont", id);
     unsafe {
+        core::LLVMBuildBr(*builder, cond_block);
+        core::LLVMPositionBuilderAtEnd(*builder, cond_block); // START COND
+        let i = core::LLVMBuildLoad2(*builder, ty, counter, c_str_ptr!(""));
+        let predicate = if unsigned { LLVMIntPredicate::LLVMIntULT } else { LLVMIntPredicate::LLVMIntSLT };
+        let cond_val = core::LLVMBuildICmp(*builder, predicate, i, count, c_str_ptr!(""));
         core::LLVMBuildCondBr(*builder, cond_val, body_block, continue_block); // END COND
         core::LLVMPositionBuilderAtEnd(*builder, body_block); // START BODY
     }
-    let mut body_local_varmap = local_varmap.clone();
+    loops.push((step_block, continue_block));
+    local_varmap.push_scope();
+    let mut body_scope = HashSet::new();
     let mut does_return = false;
     while {
         let n = ident_next!(tokens, "end");
         tokens.index -= 1;
         &n != "end"
     } {
-        if compile_statement(tokens, module, builder, function, varmap, &mut body_local_varmap)? {
+        if compile_statement(tokens, module, builder, function, varmap, local_varmap, &mut body_scope, loops)? {
             does_return = true;
         }
     }
     expect_ident!(tokens, "end");
+    loops.pop();
+    local_varmap.pop_scope();
+
+    unsafe {
+        if !does_return {
+            core::LLVMBuildBr(*builder, step_block); // END BODY
+        }
+        core::LLVMPositionBuilderAtEnd(*builder, step_block); // START STEP
+        let i = core::LLVMBuildLoad2(*builder, ty, counter, c_str_ptr!(""));
+        let next = core::LLVMBuildAdd(*builder, i, core::LLVMConstInt(ty, 1, 0), c_str_ptr!(""));
+        core::LLVMBuildStore(*builder, next, counter);
+        core::LLVMBuildBr(*builder, cond_block); // END STEP
+        core::LLVMPositionBuilderAtEnd(*builder, continue_block); // CONTINUE
+    }
+    Ok(())
+}
+
+/// an alloca at the start of the entry block, one in a loop body would grow the stack on every iteration
+fn entry_alloca(function: &LLVMValueRef, ty: LLVMTypeRef, name: &str) -> LLVMValueRef {
+    unsafe {
+        let entry = core::LLVMGetEntryBasicBlock(*function);
+        let builder = Builder::at_end(core::LLVMGetTypeContext(ty), entry);
+        let first = core::LLVMGetFirstInstruction(entry);
+        if !first.is_null() {
+            core::LLVMPositionBuilderBefore(*builder, first);
+        }
+        core::LLVMBuildAlloca(*builder, ty, c_str_ptr!(name))
+    }
+}
+
+/// lets of the body are not visible in the condition
+fn compile_repeat_until(tokens: &mut TokIter, module: &prelude::LLVMModuleRef, builder: &prelude::LLVMBuilderRef, function: &LLVMValueRef,
+                        varmap: &mut VarMap,
+                        local_varmap: &mut LocalVarMap,
+                        loops: &mut LoopStack) -> Result<(), ParseError> {
+    expect_ident!(tokens, "do");
+    let id = next_block_id(function);
+    let body_block = append_block(function, "body", id);
+    let cond_block = append_block(function, "cond", id);
+    let continue_block = append_block(function, "untilcont", id);
+    unsafe {
+        core::LLVMBuildBr(*builder, body_block);
+        core::LLVMPositionBuilderAtEnd(*builder, body_block); // START BODY
+    }
+    loops.push((cond_block, continue_block));
+    local_varmap.push_scope();
+    let mut body_scope = HashSet::new();
+    let mut does_return = false;
+    while {
+        let n = ident_next!(tokens, "until");
+        tokens.index -= 1;
+        &n != "until"
+    } {
+        if compile_statement(tokens, module, builder, function, varmap, local_varmap, &mut body_scope, loops)? {
+            does_return = true;
+        }
+    }
+    expect_ident!(tokens, "until");
+    loops.pop();
+    local_varmap.pop_scope();
 
     unsafe {
         if !does_return {
             core::LLVMBuildBr(*builder, cond_block); // END BODY
         }
+        core::LLVMPositionBuilderAtEnd(*builder, cond_block); // START COND
+    }
+    let cond_val = compile_condition(tokens, module, builder, varmap, local_varmap)?;
+    expect_ident!(tokens, "end");
+    unsafe {
+        core::LLVMBuildCondBr(*builder, cond_val, continue_block, body_block); // END COND
+        core::LLVMPositionBuilderAtEnd(*builder, continue_block); // CONTINUE
+    }
+    Ok(())
+}
+
+fn compile_for(tokens: &mut TokIter, module: &prelude::LLVMModuleRef, builder: &prelude::LLVMBuilderRef, function: &LLVMValueRef,
+               varmap: &mut VarMap,
+               local_varmap: &mut LocalVarMap,
+               loops: &mut LoopStack) -> Result<(), ParseError> {
+    let name_tok = tokens.this()?;
+    let name = ident_next!(tokens, "loop variable");
+    expect_ident!(tokens, "from");
+    let (start, unsigned) = compile_operand(tokens, module, builder, varmap, local_varmap, &name.as_str())?;
+    let ty = unsafe { core::LLVMTypeOf(start) };
+    let mut bound = |tokens: &mut TokIter, what: &str| -> Result<LLVMValueRef, ParseError> {
+        let loc = tokens.this()?.loc;
+        let v = compile_expression(tokens, module, builder, varmap, local_varmap, "")?;
+        unsafe {
+            if core::LLVMGetTypeKind(ty) != LLVMTypeKind::LLVMIntegerTypeKind || core::LLVMTypeOf(v) != ty {
+                return Err(ParseET::ParseError(format!("integer {what} of type {}", ty_to_string(ty)), ty_to_string(core::LLVMTypeOf(v)))
+                    .at(loc).when(&format!("compiling for loop over `{name}`")))
+            }
+        }
+        Ok(v)
+    };
+    expect_ident!(tokens, "to");
+    let end = bound(tokens, "end")?;
+    let step = if tokens.this()?.tt == TokenType::Ident(Symbol::intern("step")) {
+        tokens.next();
+        bound(tokens, "step")?
+    } else {
+        unsafe { core::LLVMConstInt(ty, 1, 0) }
+    };
+    expect_ident!(tokens, "do");
+    let counter = entry_alloca(function, ty, &name.as_str());
+    unsafe { core::LLVMBuildStore(*builder, start, counter); }
+    let id = next_block_id(function);
+    let cond_block = append_block(function, "cond", id);
+    let body_block = append_block(function, "body", id);
+    let step_block = append_block(function, "step", id);
+    let continue_block = append_block(function, "forcont", id);
+    unsafe {
+        core::LLVMBuildBr(*builder, cond_block);
+        core::LLVMPositionBuilderAtEnd(*builder, cond_block); // START COND
+        let i = core::LLVMBuildLoad2(*builder, ty, counter, c_str_ptr!(""));
+        let predicate = if unsigned { LLVMIntPredicate::LLVMIntULT } else { LLVMIntPredicate::LLVMIntSLT };
+        let cond_val = core::LLVMBuildICmp(*builder, predicate, i, end, c_str_ptr!(""));
+        core::LLVMBuildCondBr(*builder, cond_val, body_block, continue_block); // END COND
+        core::LLVMPositionBuilderAtEnd(*builder, body_block); // START BODY
+    }
+    loops.push((step_block, continue_block));
+    local_varmap.push_scope();
+    let mut body_scope = HashSet::new();
+    declare(name_tok, &mut body_scope);
+    local_varmap.insert(name, (ty, counter, true, unsigned, None));
+    let mut does_return = false;
+    while {
+        let n = ident_next!(tokens, "end");
+        tokens.index -= 1;
+        &n != "end"
+    } {
+        if compile_statement(tokens, module, builder, function, varmap, local_varmap, &mut body_scope, loops)? {
+            does_return = true;
+        }
+    }
+    expect_ident!(tokens, "end");
+    loops.pop();
+    local_varmap.pop_scope();
+
+    unsafe {
+        if !does_return {
+            core::LLVMBuildBr(*builder, step_block); // END BODY
+        }
+        core::LLVMPositionBuilderAtEnd(*builder, step_block); // START STEP
+        let i = core::LLVMBuildLoad2(*builder, ty, counter, c_str_ptr!(""));
+        let next = core::LLVMBuildAdd(*builder, i, step, c_str_ptr!(""));
+        core::LLVMBuildStore(*builder, next, counter);
+        core::LLVMBuildBr(*builder, cond_block); // END STEP
         core::LLVMPositionBuilderAtEnd(*builder, continue_block); // CONTINUE
     }
     Ok(())
 }
 
 fn compile_if(tokens: &mut TokIter, module: &prelude::LLVMModuleRef, builder: &prelude::LLVMBuilderRef, function: &LLVMValueRef,
-              varmap: &mut HashMap<String, (LLVMTypeRef, LLVMValueRef, bool)>,
-              local_varmap: &mut HashMap<String, (LLVMTypeRef, LLVMValueRef, bool)>) -> Result<(), ParseError> {
-    let cond_val = compile_expression(tokens, module, builder, varmap, local_varmap, "")?;
+              varmap: &mut VarMap,
+              local_varmap: &mut LocalVarMap,
+              loops: &mut LoopStack) -> Result<bool, ParseError> {
+    let cond_val = compile_condition(tokens, module, builder, varmap, local_varmap)?;
     expect_ident!(tokens, "do");
-    let then_block = unsafe { core::LLVMAppendBasicBlock(*function, c_str_ptr!("then")) };
-    let else_block = unsafe { core::LLVMAppendBasicBlock(*function, c_str_ptr!("else")) };
-    let continue_block = unsafe { core::LLVMAppendBasicBlock(*function, c_str_ptr!("ifcont")) };
+    let id = next_block_id(function);
+    let then_block = append_block(function, "then", id);
+    let else_block = append_block(function, "else", id);
+    let continue_block = append_block(function, "ifcont", id);
     unsafe {
         core::LLVMBuildCondBr(*builder, cond_val, then_block, else_block); // IF CONDITION CALL
         core::LLVMPositionBuilderAtEnd(*builder, then_block); // START THEN CLAUSE
     };
-    let mut then_local_varmap = local_varmap.clone();
+    local_varmap.push_scope();
+    let mut then_scope = HashSet::new();
     let mut does_return = false;
     while {
         let n = ident_next!(tokens, "[end|else|elif]");
         tokens.index -= 1;
         !(n == "end" || n == "else" || n == "elif")
     }{
-        if compile_statement(tokens, module, builder, function, varmap, &mut then_local_varmap)? {
+        if compile_statement(tokens, module, builder, function, varmap, local_varmap, &mut then_scope, loops)? {
             does_return = true;
         }
     }
     let continuator = ident_next!(tokens, "[end|else|elif]");
+    let then_declared = local_varmap.pop_scope();
+    let then_end = (!does_return).then(|| unsafe { core::LLVMGetInsertBlock(*builder) });
     unsafe {
         if !does_return {
             core::LLVMBuildBr(*builder, continue_block); // END THEN CLAUSE
         }
         core::LLVMPositionBuilderAtEnd(*builder, else_block); // START ELSE CLAUSE
     }
-    let mut else_local_varmap = local_varmap.clone();
+    local_varmap.push_scope();
+    let mut else_scope = HashSet::new();
     let mut does_return = false;
     if continuator != "end" {
         if continuator == "elif" {
-            compile_if(tokens, module, builder, function, varmap, &mut else_local_varmap)?;
+            does_return = compile_if(tokens, module, builder, function, varmap, local_varmap, loops)?;
             tokens.index -= 1;
         } else {
             while {
@@ -357,134 +1782,1873 @@ fn compile_if(tokens: &mut TokIter, module: &prelude::LLVMModuleRef, builder: &p
                 tokens.index -= 1;
                 &n != "end"
             } {
-                if compile_statement(tokens, module, builder, function, varmap, &mut else_local_varmap)? {
+                if compile_statement(tokens, module, builder, function, varmap, local_varmap, &mut else_scope, loops)? {
                     does_return = true;
                 }
             }
         }
         expect_ident!(tokens, "end");
     }
+    let else_declared = local_varmap.pop_scope();
+    let else_end = (!does_return).then(|| unsafe { core::LLVMGetInsertBlock(*builder) });
     unsafe {
         if !does_return {
             core::LLVMBuildBr(*builder, continue_block); // END ELSE CLAUSE
         }
         core::LLVMPositionBuilderAtEnd(*builder, continue_block);
+        merge_branch_lets(builder, local_varmap, &[(then_declared, then_end), (else_declared, else_end)]);
     }
-    Ok(())
+    // both branches returned, nothing can reach the continue block
+    Ok(position_at_continue(builder, continue_block))
 }
 
-fn compile_fn_call(tokens: &mut TokIter, module: &prelude::LLVMModuleRef, builder: &prelude::LLVMBuilderRef,
-                    varmap: &mut HashMap<String, (LLVMTypeRef, LLVMValueRef, bool)>,
-                    local_varmap: &mut HashMap<String, (LLVMTypeRef, LLVMValueRef, bool)>,
-                    ret_name: &str) -> Result<LLVMValueRef, ParseError> {
-    let name_tt = tokens.this()?.tt;
-    let name = if let TokenType::Particle(p, _) = name_tt {
-        let mut op = p.to_string();
-        tokens.next();
-        while let TokenType::Particle(p, true) = tokens.this()?.tt {
-            op.push(p);
-            tokens.next()
-        }
-        op
-    } else {
-        ident_next!(tokens, "name")
-    };
-    let n = ident_next!(tokens, "[with|end]");
-    let mut args = vec![];
-    if &n == "with" {
-        while {
-            let i = ident_next!(tokens, "[<arg>|end]");
-            if i != "end" {
-                tokens.index -= 1;
-                args.push(compile_expression(tokens, module, builder, varmap, local_varmap, "")?);
-                true
-            } else { false }
-        } {}
-    }
-    let r = if let TokenType::Particle(p, _) = name_tt{
-        let b = args.pop().expect(&format!("no arg 1 for bin op {name}"));
-        let a = args.pop().expect(&format!("no arg 2 for binary op {name}"));
-        unsafe {
-            match name.as_str() {
-                "+" => core::LLVMBuildAdd(*builder, a, b, c_str_ptr!(ret_name)),
-                "-" => core::LLVMBuildSub(*builder, a, b, c_str_ptr!(ret_name)),
-                "*" => core::LLVMBuildMul(*builder, a, b, c_str_ptr!(ret_name)),
-                "/" => core::LLVMBuildSDiv(*builder, a, b, c_str_ptr!(ret_name)),
-                "&" => core::LLVMBuildAnd(*builder, a, b, c_str_ptr!(ret_name)),
-                "|" => core::LLVMBuildOr(*builder, a, b, c_str_ptr!(ret_name)),
-
-                ">" => core::LLVMBuildICmp(*builder, LLVMIntPredicate::LLVMIntSGT, a, b, c_str_ptr!(ret_name)),
-                ">=" => core::LLVMBuildICmp(*builder, LLVMIntPredicate::LLVMIntSGE, a, b, c_str_ptr!(ret_name)),
-                "<" => core::LLVMBuildICmp(*builder, LLVMIntPredicate::LLVMIntSLT, a, b, c_str_ptr!(ret_name)),
-                "<=" => core::LLVMBuildICmp(*builder, LLVMIntPredicate::LLVMIntSLE, a, b, c_str_ptr!(ret_name)),
-                "==" => core::LLVMBuildICmp(*builder, LLVMIntPredicate::LLVMIntEQ, a, b, c_str_ptr!(ret_name)),
-                "!=" => core::LLVMBuildICmp(*builder, LLVMIntPredicate::LLVMIntNE, a, b, c_str_ptr!(ret_name)),
-                c => unreachable!("unknown literal func {c}")
+/// lets that every branch reaching the continue block declares with the same type stay visible after it
+fn merge_branch_lets(builder: &prelude::LLVMBuilderRef, local_varmap: &mut LocalVarMap, branches: &[(VarMap, Option<LLVMBasicBlockRef>)]) {
+    let reaching: Vec<_> = branches.iter().filter_map(|(m, end)| end.map(|end| (m, end))).collect();
+    let Some((first, _)) = reaching.first() else { return };
+    let declared_in = |m: &VarMap, name: &Symbol| m.get(name)
+        .filter(|(_, v, is_alloca, _, _)| !is_alloca && local_varmap.get(name).map(|outer| outer.1) != Some(*v))
+        .copied();
+    let mut names: Vec<_> = first.keys().filter(|name| declared_in(first, name).is_some()).copied().collect();
+    names.sort_by_key(|name| name.as_str());
+    let mut merged = vec![];
+    for name in names {
+        let (ty, _, _, unsigned, pointee) = declared_in(first, &name).unwrap();
+        let incoming: Option<Vec<_>> = reaching.iter()
+            .map(|(m, end)| declared_in(m, &name).filter(|e| e.0 == ty).map(|e| (e.1, *end)))
+            .collect();
+        let Some(incoming) = incoming else { continue };
+        let v = if let [(v, _)] = incoming[..] {
+            v
+        } else {
+            unsafe {
+                let phi = core::LLVMBuildPhi(*builder, ty, c_str_ptr!(name));
+                let (mut values, mut blocks): (Vec<_>, Vec<_>) = incoming.into_iter().unzip();
+                core::LLVMAddIncoming(phi, values.as_mut_ptr(), blocks.as_mut_ptr(), values.len() as c_uint);
+                phi
             }
-        }
-    } else {
-        let fun = get_var(&name, tokens.this()?.loc, varmap, local_varmap)?;
-        unsafe { core::LLVMBuildCall2(*builder, fun.0, fun.1, args.as_mut_ptr(), args.len() as c_uint, c_str_ptr!(ret_name)) }
-    };
-    Ok(r)
+        };
+        merged.push((name, (ty, v, false, unsigned, pointee)));
+    }
+    for (name, var) in merged {
+        local_varmap.insert(name, var);
+    }
+}
+
+fn compile_guard(tokens: &mut TokIter, module: &prelude::LLVMModuleRef, builder: &prelude::LLVMBuilderRef, function: &LLVMValueRef,
+                 varmap: &mut VarMap,
+                 local_varmap: &mut LocalVarMap,
+                 loops: &mut LoopStack) -> Result<(), ParseError> {
+    let cond_val = compile_condition(tokens, module, builder, varmap, local_varmap)?;
+    expect_ident!(tokens, "else");
+    expect_ident!(tokens, "do");
+    let id = next_block_id(function);
+    let else_block = append_block(function, "guardelse", id);
+    let continue_block = append_block(function, "guardcont", id);
+    unsafe {
+        core::LLVMBuildCondBr(*builder, cond_val, continue_block, else_block);
+        core::LLVMPositionBuilderAtEnd(*builder, else_block);
+    }
+    local_varmap.push_scope();
+    let mut else_scope = HashSet::new();
+    let mut does_return = false;
+    while tokens.this()?.tt != TokenType::Ident(Symbol::intern("end")) {
+        if compile_statement(tokens, module, builder, function, varmap, local_varmap, &mut else_scope, loops)? {
+            does_return = true;
+        }
+    }
+    local_varmap.pop_scope();
+    if !does_return {
+        return Err(ParseET::ParseError("else block to diverge with return, break or unreachable".to_string(), "end".to_string())
+            .at(tokens.this()?.loc).when("checking guard else block diverges"))
+    }
+    expect_ident!(tokens, "end");
+    unsafe { core::LLVMPositionBuilderAtEnd(*builder, continue_block) }
+    Ok(())
+}
+
+fn compile_match(tokens: &mut TokIter, module: &prelude::LLVMModuleRef, builder: &prelude::LLVMBuilderRef, function: &LLVMValueRef,
+                 varmap: &mut VarMap,
+                 local_varmap: &mut LocalVarMap,
+                 loops: &mut LoopStack) -> Result<bool, ParseError> {
+    let match_loc = tokens.this()?.loc;
+    let cond_val = compile_expression(tokens, module, builder, varmap, local_varmap, "")?;
+    let cond_ty = unsafe { core::LLVMTypeOf(cond_val) };
+    if cond_ty != unsafe { core::LLVMInt1TypeInContext(context(module)) } {
+        return Err(ParseET::ParseError("bool".to_string(), ty_to_string(cond_ty)).at(match_loc).when("compiling match"))
+    }
+    let id = next_block_id(function);
+    let true_block = append_block(function, "matchtrue", id);
+    let false_block = append_block(function, "matchfalse", id);
+    let continue_block = append_block(function, "matchcont", id);
+    unsafe {
+        core::LLVMBuildCondBr(*builder, cond_val, true_block, false_block);
+    }
+    let mut seen = [false; 2];
+    while {
+        let tok = tokens.this()?;
+        tok.tt != TokenType::Ident(Symbol::intern("end"))
+    } {
+        let tok = tokens.this()?;
+        let arm = if let TokenType::Literal(Literal::Bool(b)) = tok.tt {
+            b
+        } else {
+            return Err(ParseET::ParseError("[true|false|end]".to_string(), format!("{:?}", tok.tt)).at(tok.loc))
+        };
+        if seen[arm as usize] {
+            return Err(ParseET::ParseError(format!("one `{arm}` arm"), format!("a second `{arm}` arm")).at(tok.loc))
+        }
+        seen[arm as usize] = true;
+        tokens.next();
+        expect_particle!(tokens, '=');
+        expect_particle!(tokens, '>');
+        unsafe { core::LLVMPositionBuilderAtEnd(*builder, if arm { true_block } else { false_block }) }
+        local_varmap.push_scope();
+        let mut arm_scope = HashSet::new();
+        let mut does_return = false;
+        while {
+            let tt = tokens.this()?.tt;
+            !(matches!(tt, TokenType::Literal(Literal::Bool(_))) || tt == TokenType::Ident(Symbol::intern("end")))
+        } {
+            if compile_statement(tokens, module, builder, function, varmap, local_varmap, &mut arm_scope, loops)? {
+                does_return = true;
+            }
+        }
+        local_varmap.pop_scope();
+        if !does_return {
+            unsafe { core::LLVMBuildBr(*builder, continue_block); }
+        }
+    }
+    if let Some(missing) = seen.iter().position(|s| !s) {
+        return Err(ParseET::ParseError(format!("`{}` arm", missing == 1), "end".to_string()).at(match_loc)
+            .when("checking boolean match is exhaustive"))
+    }
+    expect_ident!(tokens, "end");
+    Ok(position_at_continue(builder, continue_block))
+}
+
+fn position_at_continue(builder: &prelude::LLVMBuilderRef, continue_block: LLVMBasicBlockRef) -> bool {
+    unsafe {
+        core::LLVMPositionBuilderAtEnd(*builder, continue_block);
+        let diverges = core::LLVMGetFirstUse(core::LLVMBasicBlockAsValue(continue_block)).is_null();
+        if diverges {
+            core::LLVMBuildUnreachable(*builder);
+        }
+        diverges
+    }
+}
+
+/// labels with the same bits like `-1` and `0xFF` for an i8 are duplicates, a failed `when` falls through to the next case with the label or the default
+fn compile_switch(tokens: &mut TokIter, module: &prelude::LLVMModuleRef, builder: &prelude::LLVMBuilderRef, function: &LLVMValueRef,
+                  varmap: &mut VarMap,
+                  local_varmap: &mut LocalVarMap,
+                  loops: &mut LoopStack) -> Result<bool, ParseError> {
+    let loc = tokens.this()?.loc;
+    let val = compile_expression(tokens, module, builder, varmap, local_varmap, "")?;
+    let ty = unsafe { core::LLVMTypeOf(val) };
+    if unsafe { core::LLVMGetTypeKind(ty) } != LLVMTypeKind::LLVMIntegerTypeKind {
+        return Err(ParseET::ParseError("integer".to_string(), ty_to_string(ty)).at(loc).when("compiling switch"))
+    }
+    expect_ident!(tokens, "do");
+    let id = next_block_id(function);
+    let default_block = append_block(function, "default", id);
+    let continue_block = append_block(function, "switchcont", id);
+    let switch = unsafe { core::LLVMBuildSwitch(*builder, val, default_block, 0) };
+    // for each label, the block its last `when` jumps to when the condition fails, connected once the next case or the default is known
+    let mut labels: HashMap<LLVMValueRef, Option<LLVMBasicBlockRef>> = HashMap::new();
+    let mut has_default = false;
+    loop {
+        let tok = tokens.this()?;
+        let block = match &*ident_next!(tokens, "[case|default|end]").as_str() {
+            "end" => break,
+            "case" if !has_default => {
+                let (value, negative, label_loc) = literal_next(tokens)?;
+                let label = match value {
+                    Literal::Number(NumLit::Integer(i), _) => format!("{}{i}", if negative { "-" } else { "" }),
+                    value => return Err(ParseET::ParseError("integer case label".to_string(), format!("{value:?}")).at(label_loc))
+                };
+                // constants are unique per value, so labels with the same bits are the same constant
+                let label_v = const_literal(ty, value, negative, label_loc.clone()).map_err(|e| e.when("compiling switch"))?;
+                let block = append_block(function, &format!("case{label}"), id);
+                let guarded = tokens.this()?.tt == TokenType::Ident(Symbol::intern("when"));
+                let entry = if guarded { append_block(function, &format!("case{label}when"), id) } else { block };
+                match labels.get(&label_v) {
+                    None => unsafe { core::LLVMAddCase(switch, label_v, entry) },
+                    Some(Some(failed)) => unsafe {
+                        core::LLVMPositionBuilderAtEnd(*builder, *failed);
+                        core::LLVMBuildBr(*builder, entry);
+                    },
+                    Some(None) => return Err(ParseET::ParseError(format!("one `case {label}` without when"), format!("a second `case {label}`")).at(label_loc)
+                        .when("compiling switch"))
+                }
+                let failed = if guarded {
+                    tokens.next();
+                    unsafe { core::LLVMPositionBuilderAtEnd(*builder, entry) }
+                    let cond_val = compile_condition(tokens, module, builder, varmap, local_varmap)?;
+                    let failed = append_block(function, &format!("case{label}else"), id);
+                    unsafe { core::LLVMBuildCondBr(*builder, cond_val, block, failed); }
+                    Some(failed)
+                } else { None };
+                labels.insert(label_v, failed);
+                block
+            }
+            "default" if !has_default => {
+                has_default = true;
+                default_block
+            }
+            found => return Err(ParseET::ParseError("end after default".to_string(), found.to_string()).at(tok.loc))
+        };
+        unsafe { core::LLVMPositionBuilderAtEnd(*builder, block) }
+        local_varmap.push_scope();
+        let mut case_scope = HashSet::new();
+        let mut does_return = false;
+        while !matches!(tokens.this()?.tt, TokenType::Ident(i) if i == "case" || i == "default" || i == "end") {
+            if compile_statement(tokens, module, builder, function, varmap, local_varmap, &mut case_scope, loops)? {
+                does_return = true;
+            }
+        }
+        local_varmap.pop_scope();
+        if !does_return {
+            unsafe { core::LLVMBuildBr(*builder, continue_block); }
+        }
+    }
+    for failed in labels.into_values().flatten() {
+        unsafe {
+            core::LLVMPositionBuilderAtEnd(*builder, failed);
+            core::LLVMBuildBr(*builder, default_block);
+        }
+    }
+    if !has_default {
+        unsafe {
+            core::LLVMPositionBuilderAtEnd(*builder, default_block);
+            core::LLVMBuildBr(*builder, continue_block);
+        }
+    }
+    Ok(position_at_continue(builder, continue_block))
+}
+
+fn compile_short_circuit(tokens: &mut TokIter, module: &prelude::LLVMModuleRef, builder: &prelude::LLVMBuilderRef,
+                         varmap: &mut VarMap,
+                         local_varmap: &mut LocalVarMap,
+                         is_and: bool,
+                         ret_name: &str) -> Result<LLVMValueRef, ParseError> {
+    let op = if is_and { "and" } else { "or" };
+    let mut operand = |tokens: &mut TokIter| -> Result<LLVMValueRef, ParseError> {
+        let loc = tokens.this()?.loc;
+        let v = compile_expression(tokens, module, builder, varmap, local_varmap, "")?;
+        unsafe {
+            if core::LLVMTypeOf(v) != core::LLVMInt1TypeInContext(context(module)) {
+                return Err(ParseET::ParseError("bool operand".to_string(), ty_to_string(core::LLVMTypeOf(v))).at(loc).when(&format!("compiling {op}")))
+            }
+        }
+        Ok(v)
+    };
+    let a = operand(tokens)?;
+    unsafe {
+        let a_block = core::LLVMGetInsertBlock(*builder);
+        let function = core::LLVMGetBasicBlockParent(a_block);
+        let id = next_block_id(&function);
+        let b_block = append_block(&function, &format!("{op}rhs"), id);
+        let continue_block = append_block(&function, &format!("{op}cont"), id);
+        if is_and {
+            core::LLVMBuildCondBr(*builder, a, b_block, continue_block);
+        } else {
+            core::LLVMBuildCondBr(*builder, a, continue_block, b_block);
+        }
+        core::LLVMPositionBuilderAtEnd(*builder, b_block);
+        let b = operand(tokens)?;
+        // the operand may have added blocks of its own, the phi needs the one it ends in
+        let b_block = core::LLVMGetInsertBlock(*builder);
+        core::LLVMBuildBr(*builder, continue_block);
+        core::LLVMPositionBuilderAtEnd(*builder, continue_block);
+        let bool_ty = core::LLVMInt1TypeInContext(context(module));
+        let phi = core::LLVMBuildPhi(*builder, bool_ty, c_str_ptr!(ret_name));
+        let mut values = [core::LLVMConstInt(bool_ty, !is_and as c_ulonglong, 0), b];
+        let mut blocks = [a_block, b_block];
+        core::LLVMAddIncoming(phi, values.as_mut_ptr(), blocks.as_mut_ptr(), 2);
+        Ok(phi)
+    }
+}
+
+fn compile_fn_call(tokens: &mut TokIter, module: &prelude::LLVMModuleRef, builder: &prelude::LLVMBuilderRef,
+                    varmap: &mut VarMap,
+                    local_varmap: &mut LocalVarMap,
+                    ret_name: &str) -> Result<(LLVMValueRef, bool), ParseError> {
+    let Token { tt: name_tt, loc: name_loc } = tokens.this()?;
+    let name = if let TokenType::Particle(p, _) = name_tt {
+        let mut op = p.to_string();
+        tokens.next();
+        while let TokenType::Particle(p, true) = tokens.this()?.tt {
+            op.push(p);
+            tokens.next()
+        }
+        Symbol::intern(&op)
+    } else {
+        ident_next!(tokens, "name")
+    };
+    if name == "and" || name == "or" {
+        expect_ident!(tokens, "with");
+        let r = compile_short_circuit(tokens, module, builder, varmap, local_varmap, name == "and", ret_name)?;
+        expect_ident!(tokens, "end");
+        return Ok((r, false))
+    }
+    let n = ident_next!(tokens, "[with|end]");
+    let mut args = vec![];
+    if &n == "with" {
+        while {
+            let i = ident_next!(tokens, "[<arg>|end]");
+            if i != "end" {
+                tokens.index -= 1;
+                args.push(compile_operand(tokens, module, builder, varmap, local_varmap, "")?);
+                true
+            } else { false }
+        } {}
+    }
+    let r = if name == "!" || name == "neg" {
+        if args.len() != 1 {
+            return Err(ParseET::ParseError(format!("one operand for unary {name}"), format!("{} operands", args.len())).at(name_loc))
+        }
+        let (v, unsigned) = args.pop().unwrap();
+        unsafe {
+            match (&*name.as_str(), is_float_ty(core::LLVMTypeOf(v))) {
+                ("!", false) => (core::LLVMBuildNot(*builder, v, c_str_ptr!(ret_name)), unsigned),
+                ("neg", false) => (core::LLVMBuildNeg(*builder, v, c_str_ptr!(ret_name)), unsigned),
+                ("neg", true) => (core::LLVMBuildFNeg(*builder, v, c_str_ptr!(ret_name)), false),
+                _ => return Err(ParseET::ParseError(format!("integer operand for {name}"), ty_to_string(core::LLVMTypeOf(v))).at(name_loc))
+            }
+        }
+    } else if let TokenType::Particle(p, _) = name_tt{
+        if args.len() != 2 {
+            return Err(ParseET::ParseError(format!("two operands for binary {name}"), format!("{} operands", args.len())).at(name_loc))
+        }
+        let (b, b_unsigned) = args.pop().unwrap();
+        let (a, a_unsigned) = args.pop().unwrap();
+        let unsigned = a_unsigned || b_unsigned;
+        let (a, b) = unify_int_widths(builder, (a, a_unsigned), (b, b_unsigned));
+        let (ta, tb) = unsafe { (core::LLVMTypeOf(a), core::LLVMTypeOf(b)) };
+        let is_ptr = |t| unsafe { core::LLVMGetTypeKind(t) } == LLVMTypeKind::LLVMPointerTypeKind;
+        if is_ptr(ta) || is_ptr(tb) {
+            // pointers of any pointee type can be compared for (in)equality, and nothing else
+            let predicate = match &*name.as_str() {
+                "==" if is_ptr(ta) && is_ptr(tb) => LLVMIntPredicate::LLVMIntEQ,
+                "!=" if is_ptr(ta) && is_ptr(tb) => LLVMIntPredicate::LLVMIntNE,
+                _ => return Err(ParseET::ParseError("two pointers compared with == or !=".to_string(), format!("{} {name} {}", ty_to_string(ta), ty_to_string(tb)))
+                    .at(name_loc).when(&format!("compiling operator {name}")))
+            };
+            return unsafe {
+                let b = core::LLVMBuildPointerCast(*builder, b, ta, c_str_ptr!(""));
+                Ok((core::LLVMBuildICmp(*builder, predicate, a, b, c_str_ptr!(ret_name)), false))
+            }
+        }
+        match (is_float_ty(ta), is_float_ty(tb)) {
+            (true, true) if ta == tb => return Ok((build_float_op(builder, name, a, b, ret_name, name_loc)?, false)),
+            (false, false) => {}
+            _ => return Err(ParseET::ParseError("operands of the same type".to_string(), format!("{} and {}", ty_to_string(ta), ty_to_string(tb)))
+                .at(name_loc).when(&format!("compiling operator {name}")))
+        }
+        if (name == "/" || name == "%") && unsafe { !core::LLVMIsAConstantInt(b).is_null() && core::LLVMConstIntGetZExtValue(b) == 0 } {
+            return Err(ParseET::ParseError("non-zero divisor".to_string(), "constant 0".to_string()).at(name_loc))
+        }
+        if unsafe { core::LLVMIsConstant(a) != 0 && core::LLVMIsConstant(b) != 0 } {
+            if let Some(r) = const_int_op(&name.as_str(), a, b, unsigned) {
+                return Ok(r)
+            }
+        }
+        let cmp = |signed, unsigned_pred| if unsigned { unsigned_pred } else { signed };
+        unsafe {
+            match &*name.as_str() {
+                "+" => (core::LLVMBuildAdd(*builder, a, b, c_str_ptr!(ret_name)), unsigned),
+                "-" => (core::LLVMBuildSub(*builder, a, b, c_str_ptr!(ret_name)), unsigned),
+                "*" => (core::LLVMBuildMul(*builder, a, b, c_str_ptr!(ret_name)), unsigned),
+                "/" if unsigned => (core::LLVMBuildUDiv(*builder, a, b, c_str_ptr!(ret_name)), unsigned),
+                "/" => (core::LLVMBuildSDiv(*builder, a, b, c_str_ptr!(ret_name)), unsigned),
+                "%" if unsigned => (core::LLVMBuildURem(*builder, a, b, c_str_ptr!(ret_name)), unsigned),
+                "%" => (core::LLVMBuildSRem(*builder, a, b, c_str_ptr!(ret_name)), unsigned),
+                "&" => (core::LLVMBuildAnd(*builder, a, b, c_str_ptr!(ret_name)), unsigned),
+                "|" => (core::LLVMBuildOr(*builder, a, b, c_str_ptr!(ret_name)), unsigned),
+                "^" => (core::LLVMBuildXor(*builder, a, b, c_str_ptr!(ret_name)), unsigned),
+                "<<" => (core::LLVMBuildShl(*builder, a, b, c_str_ptr!(ret_name)), unsigned),
+                ">>" if unsigned => (core::LLVMBuildLShr(*builder, a, b, c_str_ptr!(ret_name)), unsigned),
+                ">>" => (core::LLVMBuildAShr(*builder, a, b, c_str_ptr!(ret_name)), unsigned),
+
+                ">" => (core::LLVMBuildICmp(*builder, cmp(LLVMIntPredicate::LLVMIntSGT, LLVMIntPredicate::LLVMIntUGT), a, b, c_str_ptr!(ret_name)), false),
+                ">=" => (core::LLVMBuildICmp(*builder, cmp(LLVMIntPredicate::LLVMIntSGE, LLVMIntPredicate::LLVMIntUGE), a, b, c_str_ptr!(ret_name)), false),
+                "<" => (core::LLVMBuildICmp(*builder, cmp(LLVMIntPredicate::LLVMIntSLT, LLVMIntPredicate::LLVMIntULT), a, b, c_str_ptr!(ret_name)), false),
+                "<=" => (core::LLVMBuildICmp(*builder, cmp(LLVMIntPredicate::LLVMIntSLE, LLVMIntPredicate::LLVMIntULE), a, b, c_str_ptr!(ret_name)), false),
+                "==" => (core::LLVMBuildICmp(*builder, LLVMIntPredicate::LLVMIntEQ, a, b, c_str_ptr!(ret_name)), false),
+                "!=" => (core::LLVMBuildICmp(*builder, LLVMIntPredicate::LLVMIntNE, a, b, c_str_ptr!(ret_name)), false),
+                c => return Err(ParseET::ParseError("operator".to_string(), format!("`{c}`")).at(name_loc).when("compiling call"))
+            }
+        }
+    } else {
+        let (ty, v, is_alloca, _, pointee) = get_var(name, name_loc.clone(), varmap, local_varmap)
+            .map_err(|e| e.when(&format!("looking up function {name}")))?;
+        let (fn_ty, callee) = unsafe {
+            match (core::LLVMGetTypeKind(ty), pointee) {
+                (LLVMTypeKind::LLVMFunctionTypeKind, _) => (ty, v),
+                // a variable of a `fn` type
+                (LLVMTypeKind::LLVMPointerTypeKind, Some(sig)) if core::LLVMGetTypeKind(sig) == LLVMTypeKind::LLVMFunctionTypeKind => {
+                    let callee = if is_alloca { core::LLVMBuildLoad2(*builder, ty, v, c_str_ptr!("")) } else { v };
+                    (sig, callee)
+                }
+                _ => return Err(ParseET::NotCallable(name.to_string()).at(name_loc))
+            }
+        };
+        let mut args: Vec<_> = args.into_iter().map(|(v, _)| v).collect();
+        check_call_args(fn_ty, &args).map_err(|e| e.at(name_loc).when(&format!("calling {name}")))?;
+        (unsafe { core::LLVMBuildCall2(*builder, fn_ty, callee, args.as_mut_ptr(), args.len() as c_uint, c_str_ptr!(ret_name)) }, false)
+    };
+    Ok(r)
+}
+
+/// folds an operator on two integer constants, division and remainder have no constant expressions in llvm 15 and are left to the builder
+fn const_int_op(op: &str, a: LLVMValueRef, b: LLVMValueRef, unsigned: bool) -> Option<(LLVMValueRef, bool)> {
+    let cmp = |signed, unsigned_pred| if unsigned { unsigned_pred } else { signed };
+    unsafe {
+        Some(match op {
+            "+" => (core::LLVMConstAdd(a, b), unsigned),
+            "-" => (core::LLVMConstSub(a, b), unsigned),
+            "*" => (core::LLVMConstMul(a, b), unsigned),
+            "&" => (core::LLVMConstAnd(a, b), unsigned),
+            "|" => (core::LLVMConstOr(a, b), unsigned),
+            "^" => (core::LLVMConstXor(a, b), unsigned),
+            "<<" => (core::LLVMConstShl(a, b), unsigned),
+            ">>" if unsigned => (core::LLVMConstLShr(a, b), unsigned),
+            ">>" => (core::LLVMConstAShr(a, b), unsigned),
+
+            ">" => (core::LLVMConstICmp(cmp(LLVMIntPredicate::LLVMIntSGT, LLVMIntPredicate::LLVMIntUGT), a, b), false),
+            ">=" => (core::LLVMConstICmp(cmp(LLVMIntPredicate::LLVMIntSGE, LLVMIntPredicate::LLVMIntUGE), a, b), false),
+            "<" => (core::LLVMConstICmp(cmp(LLVMIntPredicate::LLVMIntSLT, LLVMIntPredicate::LLVMIntULT), a, b), false),
+            "<=" => (core::LLVMConstICmp(cmp(LLVMIntPredicate::LLVMIntSLE, LLVMIntPredicate::LLVMIntULE), a, b), false),
+            "==" => (core::LLVMConstICmp(LLVMIntPredicate::LLVMIntEQ, a, b), false),
+            "!=" => (core::LLVMConstICmp(LLVMIntPredicate::LLVMIntNE, a, b), false),
+            _ => return None
+        })
+    }
+}
+
+fn check_call_args(fn_ty: LLVMTypeRef, args: &[LLVMValueRef]) -> Result<(), ParseError> {
+    unsafe {
+        let count = core::LLVMCountParamTypes(fn_ty) as usize;
+        let vararg = core::LLVMIsFunctionVarArg(fn_ty) != 0;
+        if args.len() < count || (!vararg && args.len() > count) {
+            let expected = if vararg { format!("at least {count} arguments") } else { format!("{count} arguments") };
+            return Err(ParseET::ParseError(expected, args.len().to_string()).error())
+        }
+        let mut params = vec![std::ptr::null_mut(); count];
+        core::LLVMGetParamTypes(fn_ty, params.as_mut_ptr());
+        for (i, (param, arg)) in params.into_iter().zip(args).enumerate() {
+            let arg_ty = core::LLVMTypeOf(*arg);
+            if arg_ty != param {
+                return Err(ParseET::ParseError(format!("argument {i} to be {}", ty_to_string(param)), ty_to_string(arg_ty)).error())
+            }
+        }
+    }
+    Ok(())
+}
+
+fn is_float_ty(ty: LLVMTypeRef) -> bool {
+    matches!(unsafe { core::LLVMGetTypeKind(ty) }, LLVMTypeKind::LLVMFloatTypeKind | LLVMTypeKind::LLVMDoubleTypeKind)
+}
+
+/// comparisons are ordered except `!=`, which is also true for NaN
+fn build_float_op(builder: &prelude::LLVMBuilderRef, name: Symbol, a: LLVMValueRef, b: LLVMValueRef, ret_name: &str, loc: Span) -> Result<LLVMValueRef, ParseError> {
+    unsafe {
+        Ok(match &*name.as_str() {
+            "+" => core::LLVMBuildFAdd(*builder, a, b, c_str_ptr!(ret_name)),
+            "-" => core::LLVMBuildFSub(*builder, a, b, c_str_ptr!(ret_name)),
+            "*" => core::LLVMBuildFMul(*builder, a, b, c_str_ptr!(ret_name)),
+            "/" => core::LLVMBuildFDiv(*builder, a, b, c_str_ptr!(ret_name)),
+            "%" => core::LLVMBuildFRem(*builder, a, b, c_str_ptr!(ret_name)),
+
+            ">" => core::LLVMBuildFCmp(*builder, LLVMRealPredicate::LLVMRealOGT, a, b, c_str_ptr!(ret_name)),
+            ">=" => core::LLVMBuildFCmp(*builder, LLVMRealPredicate::LLVMRealOGE, a, b, c_str_ptr!(ret_name)),
+            "<" => core::LLVMBuildFCmp(*builder, LLVMRealPredicate::LLVMRealOLT, a, b, c_str_ptr!(ret_name)),
+            "<=" => core::LLVMBuildFCmp(*builder, LLVMRealPredicate::LLVMRealOLE, a, b, c_str_ptr!(ret_name)),
+            "==" => core::LLVMBuildFCmp(*builder, LLVMRealPredicate::LLVMRealOEQ, a, b, c_str_ptr!(ret_name)),
+            "!=" => core::LLVMBuildFCmp(*builder, LLVMRealPredicate::LLVMRealUNE, a, b, c_str_ptr!(ret_name)),
+            op => return Err(ParseET::ParseError(format!("integer operands for {op}"), ty_to_string(core::LLVMTypeOf(a))).at(loc))
+        })
+    }
+}
+
+fn unify_int_widths(builder: &prelude::LLVMBuilderRef, (a, a_unsigned): (LLVMValueRef, bool), (b, b_unsigned): (LLVMValueRef, bool)) -> (LLVMValueRef, LLVMValueRef) {
+    unsafe {
+        let (ta, tb) = (core::LLVMTypeOf(a), core::LLVMTypeOf(b));
+        if core::LLVMGetTypeKind(ta) != LLVMTypeKind::LLVMIntegerTypeKind || core::LLVMGetTypeKind(tb) != LLVMTypeKind::LLVMIntegerTypeKind {
+            return (a, b)
+        }
+        let extend = |v: LLVMValueRef, unsigned: bool, from: LLVMTypeRef, to: LLVMTypeRef| if unsigned || core::LLVMGetIntTypeWidth(from) == 1 {
+            core::LLVMBuildZExt(*builder, v, to, c_str_ptr!(""))
+        } else {
+            core::LLVMBuildSExt(*builder, v, to, c_str_ptr!(""))
+        };
+        match core::LLVMGetIntTypeWidth(ta).cmp(&core::LLVMGetIntTypeWidth(tb)) {
+            Ordering::Less => (extend(a, a_unsigned, ta, tb), b),
+            Ordering::Greater => (a, extend(b, b_unsigned, tb, ta)),
+            Ordering::Equal => (a, b)
+        }
+    }
 }
 
 fn compile_literal(tokens: &mut TokIter, module: &prelude::LLVMModuleRef, builder: &prelude::LLVMBuilderRef,
-                    varmap: &mut HashMap<String, (LLVMTypeRef, LLVMValueRef, bool)>,
-                    local_varmap: &mut HashMap<String, (LLVMTypeRef, LLVMValueRef, bool)>) -> Result<LLVMValueRef, ParseError> {
-    let ty = ty_str_to_ty(&ident_next!(tokens, "type"))?;
-    let (value, loc) = if let Token { tt: TokenType::Literal(lit), loc} = tokens.this()? {
-        (lit, loc)
-    } else { panic!("literal value is not a literal value") };
-    tokens.next();
+                    varmap: &mut VarMap,
+                    local_varmap: &mut LocalVarMap) -> Result<LLVMValueRef, ParseError> {
+    let ty = ty_str_to_ty(context(module), &ty_next(tokens)?.as_str())?;
+    let tok = tokens.this()?;
+    if tok.tt == TokenType::Ident(Symbol::intern("null")) {
+        tokens.next();
+        if unsafe { core::LLVMGetTypeKind(ty) } != LLVMTypeKind::LLVMPointerTypeKind {
+            return Err(ParseET::ParseError("pointer type for null".to_string(), ty_to_string(ty)).at(tok.loc))
+        }
+        return Ok(unsafe { core::LLVMConstNull(ty) })
+    }
+    let (value, negative, loc) = literal_next(tokens)?;
+    match value {
+        Literal::String(s) if !negative => Ok(unsafe { core::LLVMBuildGlobalStringPtr(*builder, c_str_ptr!(s), c_str_ptr!("")) }),
+        value => const_literal(ty, value, negative, loc)
+    }
+}
+
+fn literal_next(tokens: &mut TokIter) -> Result<(Literal, bool, Span), ParseError> {
+    let negative = matches!(tokens.this()?.tt, TokenType::Particle('-', _));
+    if negative {
+        tokens.next();
+    }
+    match tokens.this()? {
+        Token { tt: TokenType::Literal(lit), loc } => {
+            tokens.next();
+            Ok((lit, negative, loc))
+        }
+        tok => Err(ParseET::ParseError("literal".to_string(), format!("{:?}", tok.tt)).at(tok.loc))
+    }
+}
+
+fn const_literal(ty: LLVMTypeRef, value: Literal, negative: bool, loc: Span) -> Result<LLVMValueRef, ParseError> {
+    if negative && !matches!(value, Literal::Number(..)) {
+        return Err(ParseET::ParseError("number literal after -".to_string(), format!("{value:?}")).at(loc))
+    }
     let v = unsafe {
         match value {
-            Literal::String(s) => core::LLVMBuildGlobalString(*builder, c_str_ptr!(s), c_str_ptr!("")),
-            Literal::Char(c) => unimplemented!(),
-            Literal::Number(n, _) => match n {
-                NumLit::Float(f) => unimplemented!(),
-                NumLit::Integer(i) => core::LLVMConstInt(ty, i as c_ulonglong, 0)
+            Literal::String(_) => return Err(ParseET::ParseError("char, number or bool literal".to_string(), "string literal".to_string()).at(loc)),
+            Literal::Char(c) => {
+                let fits = core::LLVMGetTypeKind(ty) == LLVMTypeKind::LLVMIntegerTypeKind
+                    && core::LLVMGetIntTypeWidth(ty) >= 8
+                    && (core::LLVMGetIntTypeWidth(ty) >= 32 || (c as u32) >> core::LLVMGetIntTypeWidth(ty) == 0);
+                if !fits {
+                    return Err(ParseET::ParseError("integer type of at least 8 bits holding the char".to_string(), format!("{c:?} as {}", ty_to_string(ty))).at(loc))
+                }
+                core::LLVMConstInt(ty, c as c_ulonglong, 0)
+            }
+            Literal::Number(n, _) => match (n, core::LLVMGetTypeKind(ty)) {
+                (NumLit::Float(f), LLVMTypeKind::LLVMFloatTypeKind | LLVMTypeKind::LLVMDoubleTypeKind) => core::LLVMConstReal(ty, if negative { -f } else { f }),
+                (NumLit::Float(f), _) => return Err(ParseET::ParseError("float type for float literal".to_string(), format!("{f} as {}", ty_to_string(ty))).at(loc)),
+                (NumLit::Integer(i), LLVMTypeKind::LLVMFloatTypeKind | LLVMTypeKind::LLVMDoubleTypeKind) => core::LLVMConstReal(ty, if negative { -(i as f64) } else { i as f64 }),
+                (NumLit::Integer(i), _) => match const_int(ty, i, negative) {
+                    Some(v) => v,
+                    None => return Err(ParseET::ParseError(format!("literal fitting {}", ty_to_string(ty)), format!("{}{i}", if negative { "-" } else { "" })).at(loc))
+                }
             }
-            Literal::Bool(b) => core::LLVMConstInt(core::LLVMInt1Type(), b as c_ulonglong, 0)
+            Literal::Bool(b) => core::LLVMConstInt(core::LLVMInt1TypeInContext(core::LLVMGetTypeContext(ty)), b as c_ulonglong, 0)
         }
     };
     Ok(v)
 }
 
+fn const_int(ty: LLVMTypeRef, i: u128, negative: bool) -> Option<LLVMValueRef> {
+    let width = unsafe { core::LLVMGetIntTypeWidth(ty) };
+    // any bit pattern of the type is fine, so `0xFF` fits an i8 as well as -128 does
+    let fits = width >= 128 || if negative { i <= 1 << (width - 1) } else { i >> width == 0 };
+    if !fits {
+        return None
+    }
+    // two's complement, split into the 64 bit words llvm takes for wide integers
+    let bits = if negative { i.wrapping_neg() } else { i };
+    let words = [bits as u64, (bits >> 64) as u64];
+    Some(unsafe { core::LLVMConstIntOfArbitraryPrecision(ty, words.len() as c_uint, words.as_ptr()) })
+}
+
+fn declare(tok: Token, scope: &mut HashSet<Symbol>) {
+    if let TokenType::Ident(name) = tok.tt {
+        if !scope.insert(name) {
+            ParseET::Redeclaration(name.to_string()).at(tok.loc).warn();
+        }
+    }
+}
+
 fn compile_let_create(tokens: &mut TokIter, module: &prelude::LLVMModuleRef, builder: &prelude::LLVMBuilderRef,
-                      varmap: &mut HashMap<String, (LLVMTypeRef, LLVMValueRef, bool)>,
-                      local_varmap: &mut HashMap<String, (LLVMTypeRef, LLVMValueRef, bool)>) -> Result<(), ParseError> {
-    let ty = ty_str_to_ty(&ident_next!(tokens, "type"))?;
+                      varmap: &mut VarMap,
+                      local_varmap: &mut LocalVarMap,
+                      scope: &mut HashSet<Symbol>) -> Result<(), ParseError> {
+    let ty_name = ty_next(tokens)?;
+    let ty = ty_str_to_ty(context(module), &ty_name.as_str())?;
+    declare(tokens.this()?, scope);
     let name = ident_next!(tokens, "name");
     expect_ident!(tokens, "be");
-    let v = compile_expression(tokens, module, builder, varmap, local_varmap, &name)?;
-    local_varmap.insert(name, (ty, v, false));
+    let v = compile_expression(tokens, module, builder, varmap, local_varmap, &name.as_str())?;
+    local_varmap.insert(name, (ty, v, false, is_unsigned_ty(&ty_name.as_str()), pointee_of(context(module), &ty_name.as_str())));
     Ok(())
 }
 
 fn compile_var_create(tokens: &mut TokIter, module: &prelude::LLVMModuleRef, builder: &prelude::LLVMBuilderRef,
-                      varmap: &mut HashMap<String, (LLVMTypeRef, LLVMValueRef, bool)>,
-                      local_varmap: &mut HashMap<String, (LLVMTypeRef, LLVMValueRef, bool)>) -> Result<(), ParseError> {
-    let ty = ty_str_to_ty(&ident_next!(tokens, "type"))?;
+                      varmap: &mut VarMap,
+                      local_varmap: &mut LocalVarMap,
+                      scope: &mut HashSet<Symbol>) -> Result<(), ParseError> {
+    let inferred = tokens.get(tokens.index + 1)?.tt == TokenType::Ident(Symbol::intern("is"));
+    let ty_name = if inferred { None } else { Some(ty_next(tokens)?) };
+    let ty = ty_name.map(|t| ty_str_to_ty(context(module), &t.as_str())).transpose()?;
+    declare(tokens.this()?, scope);
     let name = ident_next!(tokens, "name");
-    expect_ident!(tokens, "is");
-    let v = compile_expression(tokens, module, builder, varmap, local_varmap, &name)?;
+    let (ty, v, unsigned, pointee) = if tokens.this()?.tt == TokenType::Ident(Symbol::intern("is")) {
+        tokens.next();
+        let (v, unsigned) = compile_operand(tokens, module, builder, varmap, local_varmap, &name.as_str())?;
+        match (ty, ty_name) {
+            (Some(ty), Some(ty_name)) => (ty, v, is_unsigned_ty(&ty_name.as_str()), pointee_of(context(module), &ty_name.as_str())),
+            // a function keeps its signature, any other pointer cannot be called
+            _ if unsafe { !core::LLVMIsAFunction(v).is_null() } => (unsafe { core::LLVMTypeOf(v) }, v, false, Some(unsafe { core::LLVMGlobalGetValueType(v) })),
+            _ => (unsafe { core::LLVMTypeOf(v) }, v, unsigned, None)
+        }
+    } else {
+        // no initializer, start out zeroed
+        let ty = ty.unwrap();
+        let ty_name = ty_name.unwrap();
+        (ty, unsafe { core::LLVMConstNull(ty) }, is_unsigned_ty(&ty_name.as_str()), pointee_of(context(module), &ty_name.as_str()))
+    };
     let alloc_v = unsafe {
-        let alloc_v = core::LLVMBuildAlloca(*builder, ty, c_str_ptr!(name));
+        let function = core::LLVMGetBasicBlockParent(core::LLVMGetInsertBlock(*builder));
+        let alloc_v = entry_alloca(&function, ty, &name.as_str());
         core::LLVMBuildStore(*builder, v, alloc_v);
         alloc_v
     };
-    local_varmap.insert(name, (ty, alloc_v, true));
+    local_varmap.insert(name, (ty, alloc_v, true, unsigned, pointee));
     Ok(())
 }
 
 fn compile_var_update(tokens: &mut TokIter, module: &prelude::LLVMModuleRef, builder: &prelude::LLVMBuilderRef,
-varmap: &mut HashMap<String, (LLVMTypeRef, LLVMValueRef, bool)>,
-local_varmap: &mut HashMap<String, (LLVMTypeRef, LLVMValueRef, bool)>) -> Result<(), ParseError> {
+varmap: &mut VarMap,
+local_varmap: &mut LocalVarMap) -> Result<(), ParseError> {
+    // `update index <array> <i> to <expr>`, unless `index` is the name of the updated variable
+    if tokens.this()?.tt == TokenType::Ident(Symbol::intern("index"))
+        && tokens.get(tokens.index + 1)?.tt != TokenType::Ident(Symbol::intern("to")) {
+        tokens.next();
+        let (elem_ty, p) = compile_index_ptr(tokens, module, builder, varmap, local_varmap)?;
+        expect_ident!(tokens, "to");
+        let loc = tokens.this()?.loc;
+        let v = compile_expression(tokens, module, builder, varmap, local_varmap, "")?;
+        unsafe {
+            if core::LLVMTypeOf(v) != elem_ty {
+                return Err(ParseET::ParseError(ty_to_string(elem_ty), ty_to_string(core::LLVMTypeOf(v))).at(loc).when("updating array element"))
+            }
+            core::LLVMBuildStore(*builder, v, p);
+        }
+        return Ok(())
+    }
+    let name_loc = tokens.this()?.loc;
     let name = ident_next!(tokens, "name");
-    let (ty, alloc_v, _true) = get_var(&name, tokens.this()?.loc, varmap, local_varmap)?;
+    let (ty, alloc_v, _true, _, _) = get_var(name, tokens.this()?.loc, varmap, local_varmap)?;
+    if unsafe { !core::LLVMIsAGlobalVariable(alloc_v).is_null() && core::LLVMIsGlobalConstant(alloc_v) != 0 } {
+        return Err(ParseET::ParseError("variable".to_string(), format!("const `{name}`")).at(name_loc).when("updating"))
+    }
     expect_ident!(tokens, "to");
-    let v = compile_expression(tokens, module, builder, varmap, local_varmap, &name)?;
+    let v = compile_expression(tokens, module, builder, varmap, local_varmap, &name.as_str())?;
     unsafe {core::LLVMBuildStore(*builder, v, alloc_v);}
     Ok(())
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emit::jit_run;
+
+    fn tokens(src: &str) -> TokIter {
+        TokIter::new(tokenize(Source::from_string(src.to_string())).unwrap())
+    }
+
+    /// compiles and runs `src`, returning what its main returns
+    fn run(src: &str) -> i32 {
+        let module = compile(tokens(src), "test", &CompileOptions::default()).unwrap_or_else(|e| panic!("{e}"));
+        jit_run(*module).unwrap()
+    }
+
+    fn compile_error(src: &str) -> String {
+        match compile(tokens(src), "test", &CompileOptions::default()) {
+            Ok(_) => panic!("expected an error compiling:\n{src}"),
+            Err(e) => e.to_string()
+        }
+    }
+
+    #[test]
+    fn compile_to_string_prints_the_module() {
+        let ir = compile_to_string("fn main i32 do\n    return call + with literal i32 2 literal i32 3 end\nend\n", "small").unwrap();
+        assert!(ir.contains("ModuleID = 'small'"));
+        assert!(ir.contains("define i32 @main()"));
+        // the builder folds constant operands
+        assert!(ir.contains("ret i32 5"));
+    }
+    #[test]
+    fn match_where_every_arm_returns_needs_no_return() {
+        let src = "fn sign i32 with i32 x do
+    match call < with x literal i32 0 end
+        true => return literal i32 1
+        false => return literal i32 2
+    end
+end
+fn main i32 do
+    return call + with call sign with literal i32 -3 end call * with literal i32 10 call sign with literal i32 3 end end end
+end
+";
+        assert_eq!(run(src), 21);
+    }
+
+    #[test]
+    fn switch_where_every_case_returns_needs_no_return() {
+        let src = "fn name i32 with i32 x do
+    switch x do
+        case 0 return literal i32 10
+        case 1 return literal i32 11
+        default return literal i32 12
+    end
+end
+fn main i32 do
+    return call + with call name with literal i32 1 end call name with literal i32 7 end end
+end
+";
+        assert_eq!(run(src), 23);
+    }
+
+    #[test]
+    fn switch_without_default_still_needs_a_return() {
+        let src = "fn name i32 with i32 x do
+    switch x do
+        case 0 return literal i32 10
+    end
+end
+fn main i32 do
+    return call name with literal i32 0 end
+end
+";
+        assert!(compile_error(src).contains("Missing return"));
+    }
+    #[test]
+    fn levenshtein_counts_edits() {
+        assert_eq!(levenshtein("i32", "i32"), 0);
+        assert_eq!(levenshtein("", "ptr"), 3);
+        assert_eq!(levenshtein("i23", "i32"), 2);
+        assert_eq!(levenshtein("boool", "bool"), 1);
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn suggest_ty_finds_close_types() {
+        assert_eq!(suggest_ty("i33"), Some("i32"));
+        // one edit away from i32, i64, i8 and i16 alike
+        assert_eq!(suggest_ty("i3"), Some("i32"));
+        assert_eq!(suggest_ty("bol"), Some("bool"));
+        assert_eq!(suggest_ty("f63"), Some("f64"));
+        // as close to i8 as to i64, the more common one wins
+        assert_eq!(suggest_ty("i6"), Some("i64"));
+        assert_eq!(suggest_ty("f46"), None);
+        assert_eq!(suggest_ty("string"), None);
+    }
+
+    #[test]
+    fn unknown_parameter_type_is_an_error() {
+        let error = compile_error("fn f i32 with i33 x do\n    return x\nend\n");
+        assert!(error.contains("did you mean `i32`?"), "{error}");
+        let error = compile_error("fn f i32 with i3 x do\n    return x\nend\n");
+        assert!(error.contains("did you mean `i32`?"), "{error}");
+    }
+    #[test]
+    fn compile_and_link_resolves_externs_across_files() {
+        let lib = "fn double i32 with i32 x do\n    return call * with x literal i32 2 end\nend\n";
+        let app = "extern fn double i32 with i32 x end\nfn main i32 do\n    return call double with literal i32 21 end\nend\n";
+        let module = compile_and_link(vec![(tokens(lib), "lib".to_string()), (tokens(app), "app".to_string())]).unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(jit_run(*module).unwrap(), 42);
+    }
+
+    #[test]
+    fn compile_and_link_rejects_duplicate_definitions() {
+        let a = "fn f i32 do\n    return literal i32 1\nend\nfn main i32 do\n    return call f with end\nend\n";
+        let b = "fn f i32 do\n    return literal i32 2\nend\n";
+        let error = match compile_and_link(vec![(tokens(a), "a".to_string()), (tokens(b), "b".to_string())]) {
+            Ok(_) => panic!("linked two definitions of f"),
+            Err(e) => e.to_string()
+        };
+        assert!(error.contains("Duplicate definition") && error.contains("`f`") && error.contains("while linking b"), "{error}");
+    }
+    #[test]
+    fn profile_dump_prints_counts_as_long_long() {
+        let src = "fn f do\n    return end\nend\nfn main do\n    call f with end\nend\n";
+        let module = compile(tokens(src), "test", &CompileOptions { profile: true, ..Default::default() }).unwrap_or_else(|e| panic!("{e}"));
+        let ir = unsafe {
+            let ir = core::LLVMPrintModuleToString(*module);
+            let r = CStr::from_ptr(ir).to_string_lossy().into_owned();
+            core::LLVMDisposeMessage(ir);
+            r
+        };
+        // the counters are i64, which `%ld` only matches where long is 64 bit
+        assert!(ir.contains("%s: %lld calls"), "{ir}");
+        assert!(ir.contains("@f.calls"));
+    }
+    #[test]
+    fn assert_size_uses_the_target_data_layout() {
+        let ir = compile_to_string("assert_size i64 8\nfn main do\nend\n", "sizes").unwrap_or_else(|e| panic!("{e}"));
+        assert!(ir.contains("target datalayout = ") && ir.contains("target triple = "), "{ir}");
+        assert!(compile_error("assert_size i32 8\nfn main do\nend\n").contains("to be 8 bytes found 4 bytes"));
+    }
+    #[test]
+    fn internal_errors_name_the_block_being_built() {
+        let module = Module::new("test");
+        unsafe {
+            let fn_ty = core::LLVMFunctionType(core::LLVMVoidTypeInContext(module.ctx), [].as_mut_ptr(), 0, 0);
+            let function = core::LLVMAddFunction(*module, c_str_ptr!("f"), fn_ty);
+            let block = core::LLVMAppendBasicBlockInContext(module.ctx, function, c_str_ptr!("body.3"));
+            let builder = Builder::at_end(module.ctx, block);
+            let error = internal_error(&builder, "forced".to_string()).to_string();
+            assert!(error.contains("Internal compiler error") && error.contains("building block `body.3` of function `f`"), "{error}");
+        }
+    }
+
+    #[test]
+    fn unknown_operator_is_a_parse_error() {
+        let error = compile_error("fn main i32 do\n    return call ~ with literal i32 1 literal i32 2 end\nend\n");
+        assert!(error.contains("expected operator found `~`"), "{error}");
+    }
+    #[test]
+    fn repeat_counts_and_keeps_counters_in_the_entry_block() {
+        let src = "fn main i32 do
+    var i32 n is literal i32 0
+    repeat literal i32 3 do
+        for i from literal i32 0 to literal i32 4 do
+            update n to call + with n literal i32 1 end
+        end
+    end
+    return n
+end
+";
+        assert_eq!(run(src), 12);
+        let ir = compile_to_string(src, "test").unwrap();
+        let body = &ir[ir.find("define i32 @main.1()").unwrap()..];
+        let entry = &body[..body.find("\n\n").unwrap()];
+        // n, repeati and i, none of them inside a loop
+        assert_eq!(entry.matches("alloca").count(), 3, "{body}");
+        assert_eq!(body.matches("alloca").count(), 3, "{body}");
+    }
+
+    #[test]
+    fn repeat_compares_unsigned_counts_unsigned() {
+        let src = "fn main i32 do
+    var i32 n is literal i32 0
+    repeat literal u8 200 do
+        update n to call + with n literal i32 1 end
+    end
+    return n
+end
+";
+        assert_eq!(run(src), 200);
+    }
+
+    #[test]
+    fn repeat_count_has_to_be_an_integer() {
+        let error = compile_error("fn main do\n    repeat literal f64 2.0 do\n    end\nend\n");
+        assert!(error.contains("expected integer count found double"), "{error}");
+    }
+    #[test]
+    fn declared_functions_can_be_called_before_their_fn() {
+        let src = "declare fn later i32 with i32 x end
+fn main i32 do
+    return call later with literal i32 20 end
+end
+fn later i32 with i32 x do
+    return call + with x literal i32 1 end
+end
+";
+        assert_eq!(run(src), 21);
+        assert!(compile_error("declare fn later i32 end\nfn main do\nend\n").contains("expected fn later after its declaration"));
+        assert!(compile_error("declare fn later i32 end\nfn later i64 do\n    return literal i64 1\nend\n").contains("as declared"));
+    }
+
+    #[test]
+    fn only_declared_functions_take_a_body_later() {
+        let error = compile_error("extern fn puts i32 with ptr s end\nfn puts i32 with ptr s do\n    return literal i32 0\nend\n");
+        assert!(error.contains("Duplicate definition"), "{error}");
+        let prelude = compile(tokens("fn free with ptr p do\nend\nfn main do\nend\n"), "test", &CompileOptions { prelude: true, ..Default::default() });
+        assert!(prelude.err().unwrap().to_string().contains("Duplicate definition"));
+    }
+    #[test]
+    fn switch_labels_may_be_negative() {
+        let src = "fn pick i32 with i8 x do
+    switch x do
+        case -1 return literal i32 1
+        case 1 return literal i32 2
+        default return literal i32 3
+    end
+end
+fn main i32 do
+    return call + with call * with literal i32 10 call pick with literal i8 -1 end end call pick with literal i8 1 end end
+end
+";
+        assert_eq!(run(src), 12);
+    }
+
+    #[test]
+    fn switch_labels_have_to_fit_the_condition() {
+        let switch = |label: &str| format!("fn main do\n    switch literal i8 0 do\n        case {label}\n    end\nend\n");
+        assert!(compile_error(&switch("256")).contains("expected literal fitting i8 found 256"));
+        assert!(compile_error(&switch("-129")).contains("expected literal fitting i8 found -129"));
+        let error = compile_error(&switch("-1\n        case 0xFF"));
+        assert!(error.contains("a second `case 255`"), "{error}");
+    }
+    #[test]
+    fn defines_are_constants() {
+        let define = |value: &str| {
+            let options = CompileOptions { defines: HashMap::from([("X".to_string(), value.to_string())]), ..Default::default() };
+            compile(tokens("fn main i32 do\n    return cast i32 from X\nend\n"), "test", &options)
+        };
+        assert_eq!(jit_run(*define("10").unwrap()).unwrap(), 10);
+        assert_eq!(jit_run(*define("-5").unwrap()).unwrap(), -5);
+        // unsigned, so the cast zero extends
+        assert_eq!(jit_run(*define("200u8").unwrap()).unwrap(), 200);
+        assert!(define("300u8").err().unwrap().to_string().contains("expected integer fitting u8 found 300u8"));
+        assert!(define("4294967296").err().unwrap().to_string().contains("expected integer fitting i32"));
+        assert!(define("340282366920938463463374607431768211456").is_err());
+        // not a number, so a string that cannot be cast
+        assert!(define("hello").err().unwrap().to_string().contains("compiling cast"));
+    }
+    #[test]
+    fn match_needs_a_bool() {
+        let error = compile_error("fn main i32 do\n    match literal i32 7\n        true => return literal i32 1\n        false => return literal i32 2\n    end\nend\n");
+        assert!(error.contains("expected bool found i32"), "{error}");
+        assert!(compile_error("fn main do\n    match literal f64 1.0\n        true =>\n        false =>\n    end\nend\n").contains("expected bool found"));
+    }
+    #[test]
+    fn self_call_after_a_multi_word_type_is_found() {
+        let mut tokens = tokens("let ptr i32 p be call f with end\nvar fn i32 end g is call f with end\nvar x is call f with end\n");
+        let f = Symbol::intern("f");
+        let let_loc = first_statement_self_call(&tokens, f).expect("let");
+        tokens.index = 9;
+        let var_loc = first_statement_self_call(&tokens, f).expect("var");
+        tokens.index = 19;
+        let inferred_loc = first_statement_self_call(&tokens, f).expect("inferred var");
+        assert!(let_loc.start < var_loc.start && var_loc.start < inferred_loc.start);
+    }
+    #[test]
+    fn let_shadowing_a_param_uses_the_new_value() {
+        let src = "fn twice i32 with i32 x do
+    let i32 x be call * with x literal i32 2 end
+    return x
+end
+fn main i32 do
+    return call twice with literal i32 21 end
+end
+";
+        assert_eq!(run(src), 42);
+    }
+    #[test]
+    fn arg_keeps_unsigned_params_unsigned() {
+        let src = "fn count i32 with u8 n do
+    var i32 c is literal i32 0
+    repeat arg 0 do
+        update c to call + with c literal i32 1 end
+    end
+    return c
+end
+fn half u8 with u8 n do
+    return call / with arg 0 literal u8 2 end
+end
+fn main i32 do
+    return call + with call count with literal u8 200 end cast i32 from call half with literal u8 200 end end
+end
+";
+        assert_eq!(run(src), 300);
+    }
+
+    #[test]
+    fn match_without_a_false_arm_is_an_error() {
+        let error = compile_error("fn main do\n    match literal bool true\n        true =>\n    end\nend\n");
+        assert!(error.contains("expected `false` arm found end"), "{error}");
+    }
+
+    #[test]
+    fn division_by_constant_zero_is_an_error() {
+        let error = compile_error("fn f i32 with i32 x do\n    return call / with x literal i32 0 end\nend\n");
+        assert!(error.contains("expected non-zero divisor found constant 0"), "{error}");
+        let error = compile_error("fn f u8 with u8 x do\n    return call % with x literal u8 0 end\nend\n");
+        assert!(error.contains("non-zero divisor"), "{error}");
+    }
+
+    #[test]
+    fn unreachable_ends_a_block() {
+        let src = "fn pick i32 with bool b do
+    if b do
+        return literal i32 4
+    end
+    unreachable
+end
+fn main i32 do
+    return call pick with literal bool true end
+end
+";
+        assert!(compile_to_string(src, "test").unwrap().contains("unreachable"));
+        assert_eq!(run(src), 4);
+    }
+
+    #[test]
+    fn section_annotation_places_the_function() {
+        let ir = compile_to_string("fn @section(\".text.hot\") hot i32 do\n    return literal i32 1\nend\nfn main i32 do\n    return call hot with end\nend\n", "test").unwrap();
+        assert!(ir.contains("@hot() section \".text.hot\""), "{ir}");
+    }
+
+    #[test]
+    fn loop_runs_until_break() {
+        let src = "fn main i32 do
+    var i32 n is literal i32 0
+    loop
+        update n to call + with n literal i32 3 end
+        if call > with n literal i32 10 end do
+            break
+        end
+    end
+    return n
+end
+";
+        assert_eq!(run(src), 12);
+    }
+
+    #[test]
+    fn icmp_predicates_ignore_signedness() {
+        let src = "fn main i32 do
+    var i32 r is literal i32 0
+    if icmp_ult literal i32 -1 literal i32 1 do
+        update r to call + with r literal i32 1 end
+    end
+    if icmp_slt literal i32 -1 literal i32 1 do
+        update r to call + with r literal i32 10 end
+    end
+    return r
+end
+";
+        assert_eq!(run(src), 10);
+    }
+
+    #[test]
+    fn arg_reads_params_by_position() {
+        let src = "fn sub i32 with i32 a i32 b do
+    return call - with arg 0 arg 1 end
+end
+fn main i32 do
+    return call sub with literal i32 10 literal i32 3 end
+end
+";
+        assert_eq!(run(src), 7);
+        let error = compile_error("fn f i32 with i32 a do\n    return arg 1\nend\n");
+        assert!(error.contains("expected parameter index below 1 found 1"), "{error}");
+    }
+
+    #[test]
+    fn const_strings_are_pointers_to_their_first_char() {
+        let src = "extern fn strlen i64 with ptr s end
+const ptr GREETING is \"hello\"
+fn main i32 do
+    return cast i32 from call strlen with GREETING end
+end
+";
+        assert_eq!(run(src), 5);
+    }
+
+    #[test]
+    fn var_without_initializer_starts_at_zero() {
+        let src = "fn main i32 do
+    var i32 x
+    var f64 y
+    if call == with y literal f64 0.0 end do
+        return call + with x literal i32 1 end
+    end
+    return literal i32 2
+end
+";
+        assert_eq!(run(src), 1);
+    }
+
+    #[test]
+    fn extern_block_declares_every_function() {
+        let src = "extern block
+    fn abs i32 with i32 x end
+    fn strlen i64 with ptr s end
+end
+fn main i32 do
+    return call + with call abs with literal i32 -4 end cast i32 from call strlen with literal ptr \"ab\" end end
+end
+";
+        assert_eq!(run(src), 6);
+    }
+
+    #[test]
+    fn narrower_operands_are_widened_by_signedness() {
+        let src = "fn main i32 do
+    var i32 signed is call + with literal i8 -1 literal i32 10 end
+    var i32 unsigned is call + with literal u8 255 literal i32 10 end
+    return call + with signed unsigned end
+end
+";
+        assert_eq!(run(src), 274);
+    }
+
+    #[test]
+    fn blocks_of_one_construct_share_a_number() {
+        let ir = compile_to_string("fn main i32 do
+    var i32 i is literal i32 0
+    while call < with i literal i32 3 end do
+        if call == with i literal i32 1 end do
+            update i to literal i32 3
+        end
+        update i to call + with i literal i32 1 end
+    end
+    return i
+end
+", "test").unwrap();
+        for label in ["cond.1:", "body.1:", "whilecont.1:", "then.4:", "else.4:", "ifcont.4:"] {
+            assert!(ir.contains(label), "{label} in {ir}");
+        }
+    }
+
+    #[test]
+    fn abs_of_integers_and_floats() {
+        let src = "fn main i32 do
+    return call + with abs literal i32 -5 cast i32 from abs literal f64 -2.5 end
+end
+";
+        assert_eq!(run(src), 7);
+        assert!(compile_error("fn main do\n    abs literal ptr null\nend\n").contains("expected integer or float operand"));
+    }
+
+    #[test]
+    fn sqrt_sin_and_cos() {
+        let src = "fn main i32 do
+    var f64 one is call + with sin literal f64 0.0 cos literal f64 0.0 end
+    return cast i32 from call + with sqrt literal f64 16.0 one end
+end
+";
+        assert_eq!(run(src), 5);
+        assert!(compile_error("fn main do\n    sqrt literal i32 4\nend\n").contains("expected float operand found i32"));
+    }
+
+    #[test]
+    fn var_takes_the_type_of_its_initializer() {
+        let src = "fn main i32 do
+    var big is literal i64 5000000000
+    var small is cast i32 from call / with big literal i64 1000000000 end
+    return small
+end
+";
+        assert_eq!(run(src), 5);
+        let ir = compile_to_string(src, "test").unwrap();
+        assert!(ir.contains("%big = alloca i64") && ir.contains("alloca i32"), "{ir}");
+    }
+
+    #[test]
+    fn concat_of_literals_is_one_global() {
+        let ir = compile_to_string("const ptr S is concat \"ab\" concat \"cd\" \"ef\"\nfn main do\nend\n", "test").unwrap();
+        assert!(ir.contains("c\"abcdef\\00\""), "{ir}");
+        assert!(!ir.contains("c\"ab\\00\""), "{ir}");
+    }
+
+    #[test]
+    fn concat_of_runtime_strings_allocates() {
+        let src = "extern fn strlen i64 with ptr s end
+extern fn free with ptr p end
+fn join ptr with ptr a ptr b do
+    return concat a b
+end
+fn main i32 do
+    let ptr s be call join with literal ptr \"abc\" literal ptr \"de\" end
+    let i64 len be call strlen with s end
+    call free with s end
+    return cast i32 from len
+end
+";
+        assert_eq!(run(src), 5);
+    }
+
+    #[test]
+    fn env_falls_back_to_its_default() {
+        let ir = compile_to_string("const ptr S is env \"MINIMAL_LANGUAGE_SURELY_UNSET\" or \"fallback\"\nfn main do\nend\n", "test").unwrap();
+        assert!(ir.contains("c\"fallback\\00\""), "{ir}");
+        let error = compile_error("const ptr S is env \"MINIMAL_LANGUAGE_SURELY_UNSET\"\nfn main do\nend\n");
+        assert!(error.contains("environment variable `MINIMAL_LANGUAGE_SURELY_UNSET`"), "{error}");
+    }
+
+    #[test]
+    fn while_unroll_tags_the_back_edge() {
+        let src = "fn main i32 do
+    var i32 i is literal i32 0
+    while unroll 4 call < with i literal i32 8 end do
+        update i to call + with i literal i32 1 end
+    end
+    return i
+end
+";
+        let ir = compile_to_string(src, "test").unwrap();
+        assert!(ir.contains("br label %cond.1, !llvm.loop !0"), "{ir}");
+        assert!(ir.contains("!\"llvm.loop.unroll.count\", i32 4"), "{ir}");
+        assert_eq!(run(src), 8);
+    }
+
+    #[test]
+    fn guard_else_has_to_diverge() {
+        let src = "fn positive i32 with i32 x do
+    guard call > with x literal i32 0 end else do
+        return literal i32 0
+    end
+    return x
+end
+fn main i32 do
+    return call + with call positive with literal i32 -3 end call positive with literal i32 5 end end
+end
+";
+        assert_eq!(run(src), 5);
+        let error = compile_error("fn f with i32 x do\n    guard x else do\n        x\n    end\nend\n");
+        assert!(error.contains("expected else block to diverge"), "{error}");
+    }
+
+    #[test]
+    fn optimizing_folds_the_locals_away() {
+        let src = "fn main i32 do\n    var i32 x is literal i32 20\n    return call + with x literal i32 22 end\nend\n";
+        let module = compile(tokens(src), "test", &CompileOptions { opt_level: OptLevel::Aggressive, ..Default::default() }).unwrap_or_else(|e| panic!("{e}"));
+        let ir = unsafe {
+            let ir = core::LLVMPrintModuleToString(*module);
+            let r = CStr::from_ptr(ir).to_string_lossy().into_owned();
+            core::LLVMDisposeMessage(ir);
+            r
+        };
+        assert!(!ir.contains("alloca") && ir.contains("ret i32 42"), "{ir}");
+        assert_eq!(jit_run(*module).unwrap(), 42);
+    }
+
+    #[test]
+    fn float_types_and_literals() {
+        let src = "fn main i32 do
+    var f32 a is literal f32 1.5
+    var f64 b is literal f64 2.25
+    return cast i32 from call * with b literal f64 4 end
+end
+";
+        assert_eq!(run(src), 9);
+        assert!(compile_error("fn main do\n    var i32 x is literal i32 1.5\nend\n").contains("expected float type for float literal"));
+    }
+
+    #[test]
+    fn float_operators() {
+        let src = "fn main i32 do
+    var f32 x is call - with call / with literal f32 7.0 literal f32 2.0 end literal f32 0.5 end
+    if call < with x literal f32 2.5 end do
+        return literal i32 1
+    end
+    return cast i32 from x
+end
+";
+        assert_eq!(run(src), 3);
+        let error = compile_error("fn main do\n    call + with literal f32 1.0 literal f64 1.0 end\nend\n");
+        assert!(error.contains("expected operands of the same type found float and double"), "{error}");
+    }
+
+    #[test]
+    fn char_literals_are_integers() {
+        assert_eq!(run("fn main i32 do\n    return call + with literal i32 'A' literal i32 '\\n' end\nend\n"), 75);
+        assert!(compile_error("fn main do\n    var bool b is literal bool 'a'\nend\n").contains("integer type of at least 8 bits holding the char"));
+    }
+
+    #[test]
+    fn i16_is_sixteen_bits() {
+        let src = "fn main i32 do
+    var i16 x is literal i16 -300
+    return cast i32 from call * with x literal i16 2 end
+end
+";
+        assert_eq!(run(src), -600);
+        assert!(compile_error("fn main do\n    var i16 x is literal i16 65536\nend\n").contains("literal fitting i16"));
+    }
+
+    #[test]
+    fn remainder_follows_the_operand_signedness() {
+        let src = "fn main i32 do
+    var i32 signed is call % with literal i32 -7 literal i32 3 end
+    var u8 unsigned is call % with literal u8 250 literal u8 7 end
+    return call + with call * with signed literal i32 100 end cast i32 from unsigned end
+end
+";
+        assert_eq!(run(src), -95);
+    }
+
+    #[test]
+    fn xor_and_shifts() {
+        let src = "fn main i32 do
+    var i32 x is call ^ with literal i32 12 literal i32 10 end
+    var i32 y is call << with x literal i32 4 end
+    var i8 signed is call >> with literal i8 -16 literal i8 2 end
+    var u8 unsigned is call >> with literal u8 240 literal u8 2 end
+    return call + with call + with y cast i32 from signed end cast i32 from unsigned end
+end
+";
+        assert_eq!(run(src), 96 - 4 + 60);
+    }
+
+    #[test]
+    fn not_and_neg() {
+        let src = "fn main i32 do
+    if call ! with literal bool false end do
+        return call + with call neg with literal i32 5 end call ! with literal i32 0 end end
+    end
+    return literal i32 0
+end
+";
+        assert_eq!(run(src), -6);
+        assert!(compile_error("fn main do\n    call ! with literal f64 1.0 end\nend\n").contains("expected integer operand for ! found double"));
+    }
+
+    #[test]
+    fn operators_need_their_operand_count() {
+        let error = compile_error("fn main do\n    call + with literal i32 1 end\nend\n");
+        assert!(error.contains("expected two operands for binary + found 1 operands"), "{error}");
+        let error = compile_error("fn main do\n    call neg with end\nend\n");
+        assert!(error.contains("expected one operand for unary neg found 0 operands"), "{error}");
+    }
+
+    #[test]
+    fn calling_a_number_is_not_callable() {
+        let error = compile_error("fn main do\n    var i32 x is literal i32 1\n    call x with end\nend\n");
+        assert!(error.contains("`x` is not a function"), "{error}");
+        let error = compile_error("fn main do\n    call nowhere with end\nend\n");
+        assert!(error.contains("cant find variable") && error.contains("while looking up function nowhere"), "{error}");
+    }
+
+    #[test]
+    fn call_arguments_have_to_match_the_signature() {
+        let f = "fn f i32 with i32 x do\n    return x\nend\n";
+        let error = compile_error(&format!("{f}fn main do\n    call f with end\nend\n"));
+        assert!(error.contains("expected 1 arguments found 0") && error.contains("while calling f"), "{error}");
+        let error = compile_error(&format!("{f}fn main do\n    call f with literal i64 1 end\nend\n"));
+        assert!(error.contains("expected argument 0 to be i32 found i64"), "{error}");
+        let error = compile_error("extern fn printf i32 with vararg ptr fmt end\nfn main do\n    call printf with end\nend\n");
+        assert!(error.contains("expected at least 1 arguments found 0"), "{error}");
+    }
+
+    #[test]
+    fn integer_casts_extend_by_signedness() {
+        let src = "fn main i32 do
+    var i32 sext is cast i32 from literal i8 -2
+    var i32 zext is cast i32 from literal u8 254
+    var i8 trunc is cast i8 from literal i32 258
+    return call + with call + with sext zext end cast i32 from trunc end
+end
+";
+        assert_eq!(run(src), 254);
+    }
+
+    #[test]
+    fn casts_between_integers_and_floats() {
+        let src = "fn main i32 do
+    var f64 half is call / with cast f64 from literal i32 -7 literal f64 2.0 end
+    var f32 narrow is cast f32 from half
+    var f64 big is cast f64 from literal u8 200
+    return call + with cast i32 from narrow cast i32 from big end
+end
+";
+        assert_eq!(run(src), 197);
+        assert!(compile_error("fn main do\n    cast i32 from literal ptr null\nend\n").contains("expected integer or float types"));
+    }
+
+    #[test]
+    fn deref_reads_through_addr() {
+        let src = "fn main i32 do
+    var i32 x is literal i32 7
+    let ptr p be addr x
+    update x to literal i32 9
+    return deref i32 p
+end
+";
+        assert_eq!(run(src), 9);
+        let error = compile_error("fn f with i32 x do\n    addr x\nend\n");
+        assert!(error.contains("expected variable declared with var or function found `x`"), "{error}");
+    }
+
+    #[test]
+    fn typed_pointers_know_their_pointee() {
+        let src = "fn main i32 do
+    var i64 x is literal i64 40
+    var ptr i64 p is addr x
+    return cast i32 from call + with deref p literal i64 2 end
+end
+";
+        assert_eq!(run(src), 42);
+        let error = compile_error("fn main do\n    var i32 x\n    var ptr p is addr x\n    deref p\nend\n");
+        assert!(error.contains("`p` without a pointee type"), "{error}");
+    }
+
+    #[test]
+    fn arrays_are_indexed() {
+        let src = "fn main i32 do
+    var arr i32 3 a
+    update index a literal i32 1 to literal i32 5
+    return call + with index a literal i32 1 index a literal i32 2 end
+end
+";
+        assert_eq!(run(src), 5);
+        let error = compile_error("fn main do\n    var arr i8 2 a\n    index a literal i32 2\nend\n");
+        assert!(error.contains("expected index from -2 to 1 found 2"), "{error}");
+    }
+
+    #[test]
+    fn update_index_stores_elements() {
+        let src = "fn main i32 do
+    var arr i64 4 a
+    for i from literal i32 0 to literal i32 4 do
+        update index a i to cast i64 from call * with i i end
+    end
+    return cast i32 from index a literal i32 3
+end
+";
+        assert_eq!(run(src), 9);
+        let error = compile_error("fn main do\n    var arr i64 4 a\n    update index a literal i32 0 to literal i32 1\nend\n");
+        assert!(error.contains("expected i64 found i32") && error.contains("while updating array element"), "{error}");
+    }
+
+    #[test]
+    fn for_counts_up_with_a_step() {
+        let src = "fn main i32 do
+    var i32 sum is literal i32 0
+    for i from literal i32 1 to literal i32 10 step literal i32 3 do
+        update sum to call + with sum i end
+    end
+    return sum
+end
+";
+        assert_eq!(run(src), 1 + 4 + 7);
+        let error = compile_error("fn main do\n    for i from literal i32 0 to literal i64 3 do\n    end\nend\n");
+        assert!(error.contains("expected integer end of type i32 found i64"), "{error}");
+    }
+
+    #[test]
+    fn break_and_continue_target_the_innermost_loop() {
+        let src = "fn main i32 do
+    var i32 sum is literal i32 0
+    var i32 i is literal i32 0
+    while literal bool true do
+        update i to call + with i literal i32 1 end
+        if call > with i literal i32 6 end do
+            break
+        end
+        if call == with call % with i literal i32 2 end literal i32 0 end do
+            continue
+        end
+        update sum to call + with sum i end
+    end
+    return sum
+end
+";
+        assert_eq!(run(src), 1 + 3 + 5);
+        assert!(compile_error("fn main do\n    break\nend\n").contains("expected break inside a loop"));
+    }
+
+    #[test]
+    fn statements_after_return_are_unreachable() {
+        let error = compile_error("fn main i32 do\n    return literal i32 0\n    return literal i32 1\nend\n");
+        assert!(error.contains("Unreachable code") && error.contains("3:4..3:9"), "{error}");
+    }
+
+    #[test]
+    fn and_or_skip_their_second_operand() {
+        let src = "global i32 calls
+fn touch bool with bool b do
+    update calls to call + with calls literal i32 1 end
+    return b
+end
+fn main i32 do
+    var bool x is call and with literal bool false call touch with literal bool true end end
+    var bool y is call or with literal bool true call touch with literal bool false end end
+    var bool z is call and with literal bool true call touch with literal bool true end end
+    if call and with call ! with x end call and with y z end end do
+        return calls
+    end
+    return literal i32 -1
+end
+";
+        assert_eq!(run(src), 1);
+    }
+
+    #[test]
+    fn integer_main_returns_the_exit_code() {
+        let ir = compile_to_string("fn main i64 do\n    return literal i64 4294967298\nend\n", "test").unwrap();
+        assert!(ir.contains("define internal void @main.init()"), "{ir}");
+        assert_eq!(run("fn main i64 do\n    return literal i64 4294967298\nend\n"), 2);
+        assert!(compile_error("fn main f64 do\n    return literal f64 1.0\nend\n").contains("expected main to return an integer or nothing found double"));
+    }
+
+    #[test]
+    fn literal_needs_a_literal_value() {
+        let error = compile_error("fn main do\n    var i32 x is literal i32 y\nend\n");
+        assert!(error.contains("expected literal found Ident"), "{error}");
+    }
+
+    #[test]
+    fn lets_of_every_branch_are_visible_after_the_if() {
+        let src = "fn pick i32 with bool b do
+    if b do
+        let i32 x be literal i32 1
+    else
+        let i32 x be literal i32 2
+    end
+    return x
+end
+fn main i32 do
+    return call + with call pick with literal bool true end call * with literal i32 10 call pick with literal bool false end end end
+end
+";
+        assert_eq!(run(src), 21);
+        let error = compile_error("fn f i32 with bool b do\n    if b do\n        let i32 x be literal i32 1\n    end\n    return x\nend\n");
+        assert!(error.contains("cant find variable"), "{error}");
+    }
+
+    #[test]
+    fn globals_keep_their_value_between_calls() {
+        let src = "global i32 counter is literal i32 40
+fn bump do
+    update counter to call + with counter literal i32 1 end
+end
+fn main i32 do
+    call bump with end
+    call bump with end
+    return counter
+end
+";
+        assert_eq!(run(src), 42);
+    }
+
+    #[test]
+    fn number_bool_and_char_consts() {
+        let src = "const i32 N is 40
+const bool YES is true
+const i8 C is 'a'
+fn main i32 do
+    if YES do
+        return call + with N cast i32 from call - with C literal i8 95 end end
+    end
+    return literal i32 0
+end
+";
+        assert_eq!(run(src), 42);
+        let error = compile_error("const i32 N is 1\nfn main do\n    update N to literal i32 2\nend\n");
+        assert!(error.contains("expected variable found const `N`"), "{error}");
+    }
+
+    #[test]
+    fn second_definition_is_an_error() {
+        let error = compile_error("fn f do\nend\nfn f do\nend\n");
+        assert!(error.contains("`f` is already defined in this module"), "{error}");
+        let error = compile_error("global i32 g\nconst i32 g is 1\nfn main do\nend\n");
+        assert!(error.contains("`g` is already defined in this module"), "{error}");
+    }
+
+    #[test]
+    fn integer_conditions_compare_against_zero() {
+        let src = "fn main i32 do
+    var i32 n is literal i32 3
+    var i32 steps is literal i32 0
+    while n do
+        update n to call - with n literal i32 1 end
+        update steps to call + with steps literal i32 1 end
+    end
+    if literal i64 0 do
+        return literal i32 -1
+    end
+    return call + with steps cast i32 from literal bool true end
+end
+";
+        assert_eq!(run(src), 4);
+    }
+
+    #[test]
+    fn negative_literals_down_to_the_minimum() {
+        assert_eq!(run("fn main i32 do\n    return cast i32 from literal i8 -128\nend\n"), -128);
+        assert!(compile_error("fn main do\n    literal i8 -129\nend\n").contains("expected literal fitting i8 found -129"));
+        assert!(compile_error("fn main do\n    literal bool -true\nend\n").contains("expected number literal after -"));
+    }
+
+    #[test]
+    fn prefixed_literals_and_overflow() {
+        assert_eq!(run("fn main i32 do\n    return call + with call + with literal i32 0x10 literal i32 0o10 end literal i32 0b10 end\nend\n"), 26);
+        assert!(compile_error("fn main do\n    literal u8 256\nend\n").contains("expected literal fitting i8 found 256"));
+        let ir = compile_to_string("fn main do\n    var i128 x is literal i128 0x100000000000000000\nend\n", "test").unwrap();
+        assert!(ir.contains("i128 295147905179352825856"), "{ir}");
+    }
+
+    #[test]
+    fn escapes_in_string_literals() {
+        let src = "extern fn strlen i64 with ptr s end
+fn main i32 do
+    return cast i32 from call strlen with literal ptr \"a\\tb\\\\c\\\"\" end
+end
+";
+        assert_eq!(run(src), 6);
+        let ir = compile_to_string(src, "test").unwrap();
+        assert!(ir.contains("c\"a\\09b\\\\c\\22\\00\""), "{ir}");
+    }
+
+    #[test]
+    fn null_compares_equal_to_null_only() {
+        let src = "fn main i32 do
+    var i32 x
+    var ptr p is literal ptr null
+    if call == with p literal ptr null end do
+        if call != with addr x literal ptr null end do
+            return literal i32 1
+        end
+    end
+    return literal i32 0
+end
+";
+        assert_eq!(run(src), 1);
+        assert!(compile_error("fn main do\n    literal i32 null\nend\n").contains("expected pointer type for null"));
+        assert!(compile_error("fn main do\n    call < with literal ptr null literal ptr null end\nend\n").contains("two pointers compared with == or !="));
+    }
+
+    #[test]
+    fn fn_pointer_variables_are_callable() {
+        let src = "fn double i32 with i32 x do
+    return call * with x literal i32 2 end
+end
+fn apply i32 with fn i32 with i32 end f i32 x do
+    return call f with x end
+end
+fn main i32 do
+    var fn i32 with i32 end g is addr double
+    var h is double
+    return call + with call apply with g literal i32 10 end call h with literal i32 11 end end
+end
+";
+        assert_eq!(run(src), 42);
+    }
+
+    #[test]
+    fn sizeof_of_types() {
+        let src = "fn main i32 do
+    return cast i32 from call + with sizeof i64 sizeof arr i16 5 end
+end
+";
+        assert_eq!(run(src), 18);
+    }
+
+    #[test]
+    fn prelude_declares_malloc_and_free() {
+        let src = "fn main i32 do
+    let ptr p be call malloc with literal i64 8 end
+    call memset with p literal i32 7 literal i64 8 end
+    let i8 b be deref i8 p
+    call free with p end
+    return cast i32 from b
+end
+";
+        let module = compile(tokens(src), "test", &CompileOptions { prelude: true, ..Default::default() }).unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(jit_run(*module).unwrap(), 7);
+        assert!(compile_error(src).contains("while looking up function malloc"));
+    }
+
+    #[test]
+    fn modules_outlive_each_other() {
+        let a = compile(tokens("fn main i32 do\n    return literal i32 1\nend\n"), "a", &CompileOptions::default()).unwrap();
+        let b = compile(tokens("fn main i32 do\n    return literal i32 2\nend\n"), "b", &CompileOptions::default()).unwrap();
+        assert_ne!(context(&a), context(&b));
+        drop(a);
+        assert_eq!(jit_run(*b).unwrap(), 2);
+    }
+
+    #[test]
+    fn failed_compiles_drop_their_module() {
+        // the builder and module of the failed compile are disposed on the way out, nothing is left half built
+        for _ in 0..100 {
+            compile_error("fn main do\n    call nowhere with end\nend\n");
+        }
+        assert_eq!(run("fn main i32 do\n    return literal i32 1\nend\n"), 1);
+    }
+
+    #[test]
+    fn block_declarations_end_with_the_block() {
+        let src = "fn main i32 do
+    let i32 x be literal i32 1
+    if literal bool true do
+        let i32 x be literal i32 2
+        var i32 y is x
+    end
+    return x
+end
+";
+        assert_eq!(run(src), 1);
+        let error = compile_error("fn main i32 do\n    while literal bool false do\n        var i32 y\n    end\n    return y\nend\n");
+        assert!(error.contains("cant find variable:\n    y"), "{error}");
+    }
+
+    #[test]
+    fn repeat_until_runs_the_body_first() {
+        let src = "fn main i32 do
+    var i32 n is literal i32 10
+    repeat do
+        update n to call + with n literal i32 1 end
+    until call > with n literal i32 5 end end
+    return n
+end
+";
+        assert_eq!(run(src), 11);
+    }
+
+    #[test]
+    fn const_initializers_fold() {
+        let src = "const i32 N is call * with literal i32 6 literal i32 7 end
+global i64 SIZE is sizeof arr i32 4
+fn main i32 do
+    return call + with N cast i32 from SIZE end
+end
+";
+        assert_eq!(run(src), 58);
+        let error = compile_error("fn f i32 do\n    return literal i32 1\nend\nconst i32 N is call f with end\n");
+        assert!(error.contains("expected constant expression found value only known at runtime"), "{error}");
+    }
+
+    #[test]
+    fn fn_sig_errors_point_at_the_bad_token() {
+        let error = compile_error("fn main i32 bogus do\nend\n");
+        assert!(error.contains("expected [with|do|end] found bogus") && error.contains("1:12..1:16"), "{error}");
+    }
+    #[test]
+    fn warnings_fail_the_compile_as_errors() {
+        let src = "fn main i32 do\n    let i32 x be literal i32 1\n    call + with x literal i32 2 end\n    return x\nend\n";
+        assert!(compile(tokens(src), "test", &CompileOptions::default()).is_ok());
+        let error = match compile(tokens(src), "test", &CompileOptions { warnings_as_errors: true, ..Default::default() }) {
+            Ok(_) => panic!("compiled with a warning"),
+            Err(e) => e.to_string()
+        };
+        assert!(error.contains("1 warnings were reported"), "{error}");
+        // a call keeps quiet, it may be made for its side effects
+        let src = "fn one i32 do\n    return literal i32 1\nend\nfn main do\n    call one with end\nend\n";
+        assert!(compile(tokens(src), "test", &CompileOptions { warnings_as_errors: true, ..Default::default() }).is_ok());
+        assert!(compile(tokens("fn main do\nend\n"), "test", &CompileOptions { warnings_as_errors: true, ..Default::default() }).is_ok());
+    }
+    #[test]
+    fn negative_constant_indices_count_from_the_end() {
+        let src = "fn main i32 do
+    var arr i32 5 buf
+    for i from literal i32 0 to literal i32 5 do
+        update index buf i to call * with i literal i32 10 end
+    end
+    update index buf -2 to literal i32 7
+    return call + with index buf -1 index buf literal i8 -2 end
+end
+";
+        assert_eq!(run(src), 47);
+        let error = compile_error("fn main do\n    var arr i8 2 a\n    index a -3\nend\n");
+        assert!(error.contains("expected index from -2 to 1 found -3"), "{error}");
+    }
+    #[test]
+    fn len_of_fixed_arrays() {
+        let src = "fn main i32 do
+    var arr i32 5 buf
+    var i64 n is len buf
+    let i32 len be literal i32 10
+    return call + with cast i32 from n len end
+end
+";
+        assert_eq!(run(src), 15);
+        let error = compile_error("fn main do\n    var i32 x\n    len x\nend\n");
+        assert!(error.contains("expected array variable declared with var found `x`") && error.contains("while taking len"), "{error}");
+    }
+    #[test]
+    fn failing_when_falls_through_to_the_next_case_or_default() {
+        let src = "fn pick i32 with i32 x i32 y do
+    switch x do
+        case 1 when call > with y literal i32 10 end
+            return literal i32 1
+        case 1 when call > with y literal i32 5 end
+            return literal i32 2
+        case 2
+            return literal i32 3
+        default
+            return literal i32 4
+    end
+end
+fn main i32 do
+    var i32 r is call pick with literal i32 1 literal i32 20 end
+    update r to call + with call * with r literal i32 10 end call pick with literal i32 1 literal i32 7 end end
+    update r to call + with call * with r literal i32 10 end call pick with literal i32 1 literal i32 0 end end
+    return call + with call * with r literal i32 10 end call pick with literal i32 2 literal i32 0 end end
+end
+";
+        assert_eq!(run(src), 1243);
+        let error = compile_error("fn f with i32 x do\n    switch x do\n        case 1\n        case 1 when literal bool true\n    end\nend\n");
+        assert!(error.contains("expected one `case 1` without when found a second `case 1`"), "{error}");
+    }    #[test]
+    fn const_arrays_hold_computed_elements() {
+        let src = "const arr i32 3 SQUARES is [call * with literal i32 1 literal i32 1 end, call * with literal i32 2 literal i32 2 end, call * with literal i32 3 literal i32 3 end]
+fn main i32 do
+    return index SQUARES 2
+end
+";
+        let ir = compile_to_string(src, "test").unwrap_or_else(|e| panic!("{e}"));
+        assert!(ir.contains("[3 x i32] [i32 1, i32 4, i32 9]"), "{ir}");
+        assert_eq!(run(src), 9);
+        let error = compile_error("const arr i32 3 A is [literal i32 1, literal i32 2]\nfn main do\nend\n");
+        assert!(error.contains("expected 3 elements found 2"), "{error}");
+        let error = compile_error("fn f i32 do\n    return literal i32 1\nend\nconst arr i32 1 A is [call f with end]\nfn main do\nend\n");
+        assert!(error.contains("value only known at runtime") && error.contains("compiling element 0"), "{error}");
+    }    #[test]
+    fn vars_in_loops_are_allocated_once_in_the_entry_block() {
+        let src = "fn main i32 do
+    var i32 sum
+    for i from literal i32 0 to literal i32 4 do
+        var i32 square is call * with i i end
+        update sum to call + with sum square end
+    end
+    return sum
+end
+";
+        assert_eq!(run(src), 14);
+        let ir = compile_to_string(src, "test").unwrap_or_else(|e| panic!("{e}"));
+        let main = &ir[ir.find("define i32 @main.1").unwrap()..];
+        let main = &main[..main.find("\n}").unwrap()];
+        let entry = &main[..main.find("br ").unwrap()];
+        // sum, the loop counter and square, all before the loop starts
+        assert_eq!(entry.matches(" = alloca i32").count(), 3, "{ir}");
+        assert_eq!(main.matches(" = alloca").count(), 3, "{ir}");
+    }    /// `cargo test --release -- --ignored --nocapture bench_compile` prints the timings
+    #[test]
+    #[ignore]
+    fn bench_compile_many_identifiers() {
+        let mut src = String::new();
+        for f in 0..200 {
+            src += &format!("fn f{f} i32 with i32 x do\n    let i32 v{f}_0 be x\n");
+            for v in 1..50 {
+                src += &format!("    let i32 v{f}_{v} be call + with v{f}_{} literal i32 {v} end\n", v - 1);
+            }
+            src += &format!("    return v{f}_49\nend\n");
+        }
+        src += "fn main i32 do\n    return call f199 with literal i32 0 end\nend\n";
+        let start = std::time::Instant::now();
+        let tokens = tokens(&src);
+        let tokenized = start.elapsed();
+        let module = compile(tokens, "bench", &CompileOptions::default()).unwrap_or_else(|e| panic!("{e}"));
+        let compiled = start.elapsed() - tokenized;
+        assert_eq!(jit_run(*module).unwrap(), (1..50).sum::<i32>());
+        println!("{} bytes, 10000 lets: tokenized in {tokenized:?}, compiled in {compiled:?}", src.len());
+    }    #[test]
+    fn constant_operands_are_folded() {
+        let src = "fn main i32 do
+    let bool less be call < with literal i32 2 literal u32 3 end
+    let i32 sum be call + with literal i32 2 literal i32 3 end
+    return call ^ with sum call << with cast i32 from less literal i32 4 end end
+end
+";
+        let ir = compile_to_string(src, "test").unwrap_or_else(|e| panic!("{e}"));
+        let main = &ir[ir.find("define i32 @main.1").unwrap()..];
+        let main = &main[..main.find("\n}").unwrap()];
+        assert!(!main.contains(" add ") && !main.contains(" icmp ") && !main.contains(" shl ") && !main.contains(" xor "), "{ir}");
+        assert!(main.contains("ret i32 21"), "{ir}");
+        assert_eq!(run(src), 21);
+    }
+}
@@ -0,0 +1,363 @@
+use std::ffi::{c_char, CStr};
+use std::io::ErrorKind;
+use std::process::Command;
+use std::fmt::Write;
+use std::{fs, ptr};
+use llvm_sys::{analysis, core, error, execution_engine, prelude, target, target_machine, LLVMOpcode, LLVMTypeKind};
+use llvm_sys::transforms::pass_builder;
+use llvm_sys::target_machine::{LLVMCodeGenFileType, LLVMCodeGenOptLevel, LLVMCodeModel, LLVMRelocMode, LLVMTargetMachineRef};
+use crate::c_str_ptr;
+use crate::source::{ParseError, ParseET};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum OptLevel {
+    #[default]
+    None,
+    Less,
+    Default,
+    Aggressive,
+}
+
+impl OptLevel {
+    fn pipeline(self) -> &'static str {
+        match self {
+            OptLevel::None => "default<O0>",
+            OptLevel::Less => "default<O1>",
+            OptLevel::Default => "default<O2>",
+            OptLevel::Aggressive => "default<O3>",
+        }
+    }
+
+    fn codegen(self) -> LLVMCodeGenOptLevel {
+        match self {
+            OptLevel::None => LLVMCodeGenOptLevel::LLVMCodeGenLevelNone,
+            OptLevel::Less => LLVMCodeGenOptLevel::LLVMCodeGenLevelLess,
+            OptLevel::Default => LLVMCodeGenOptLevel::LLVMCodeGenLevelDefault,
+            OptLevel::Aggressive => LLVMCodeGenOptLevel::LLVMCodeGenLevelAggressive,
+        }
+    }
+}
+
+pub(crate) fn optimize(module: prelude::LLVMModuleRef, level: OptLevel) -> Result<(), ParseError> {
+    let machine = create_target_machine(module, None, level)?;
+    let err = unsafe {
+        let options = pass_builder::LLVMCreatePassBuilderOptions();
+        let err = pass_builder::LLVMRunPasses(module, c_str_ptr!(level.pipeline()), machine, options);
+        pass_builder::LLVMDisposePassBuilderOptions(options);
+        target_machine::LLVMDisposeTargetMachine(machine);
+        err
+    };
+    if !err.is_null() {
+        let msg = unsafe {
+            let msg = error::LLVMGetErrorMessage(err);
+            let s = CStr::from_ptr(msg).to_string_lossy().into_owned();
+            error::LLVMDisposeErrorMessage(msg);
+            s
+        };
+        return Err(ParseET::EmitError(msg).error().when(&format!("optimizing with {level:?}")))
+    }
+    Ok(())
+}
+
+pub(crate) fn emit_asm(module: prelude::LLVMModuleRef, path: &str, level: OptLevel) -> Result<(), ParseError> {
+    emit_to_file(module, None, path, LLVMCodeGenFileType::LLVMAssemblyFile, level)
+        .map_err(|e| e.when("emitting assembly"))
+}
+
+pub(crate) fn emit_object(module: prelude::LLVMModuleRef, triple: Option<&str>, path: &str, level: OptLevel) -> Result<(), ParseError> {
+    emit_to_file(module, triple, path, LLVMCodeGenFileType::LLVMObjectFile, level)
+        .map_err(|e| e.when("emitting object file"))
+}
+
+pub(crate) fn emit_ir_to_file(module: prelude::LLVMModuleRef, path: &str) -> Result<(), ParseError> {
+    verify(module).map_err(|e| e.when("emitting ir"))?;
+    let mut err = ptr::null_mut();
+    if unsafe { core::LLVMPrintModuleToFile(module, c_str_ptr!(path), &mut err) } != 0 {
+        return Err(ParseET::EmitError(take_message(err)).error().when(&format!("emitting ir to {path}")))
+    }
+    Ok(())
+}
+
+pub(crate) fn verify(module: prelude::LLVMModuleRef) -> Result<(), ParseError> {
+    let mut msg = ptr::null_mut();
+    let failed = unsafe { analysis::LLVMVerifyModule(module, analysis::LLVMVerifierFailureAction::LLVMReturnStatusAction, &mut msg) };
+    let msg = take_message(msg);
+    if failed != 0 {
+        return Err(ParseET::VerificationError(msg).error())
+    }
+    Ok(())
+}
+
+/// the module is taken back from the execution engine before it is disposed, so it stays with the caller
+pub(crate) fn jit_run(module: prelude::LLVMModuleRef) -> Result<i32, ParseError> {
+    unsafe {
+        execution_engine::LLVMLinkInMCJIT();
+        if target::LLVM_InitializeNativeTarget() != 0 || target::LLVM_InitializeNativeAsmPrinter() != 0 {
+            return Err(ParseET::EmitError("could not initialize native target".to_string()).error().when("running jit"))
+        }
+        let mut engine = ptr::null_mut();
+        let mut err = ptr::null_mut();
+        if execution_engine::LLVMCreateExecutionEngineForModule(&mut engine, module, &mut err) != 0 {
+            return Err(ParseET::EmitError(take_message(err)).error().when("creating execution engine"))
+        }
+        let dispose_engine = || {
+            let mut removed = ptr::null_mut();
+            let mut err = ptr::null_mut();
+            execution_engine::LLVMRemoveModule(engine, module, &mut removed, &mut err);
+            execution_engine::LLVMDisposeExecutionEngine(engine);
+        };
+        let mut main = ptr::null_mut();
+        if execution_engine::LLVMFindFunction(engine, c_str_ptr!("main"), &mut main) != 0 {
+            dispose_engine();
+            return Err(ParseET::EmitError("no function named `main`".to_string()).error().when("running jit"))
+        }
+        let result = execution_engine::LLVMRunFunction(engine, main, 0, ptr::null_mut());
+        let ret_ty = core::LLVMGetReturnType(core::LLVMGlobalGetValueType(main));
+        let code = if core::LLVMGetTypeKind(ret_ty) == LLVMTypeKind::LLVMVoidTypeKind {
+            0
+        } else {
+            execution_engine::LLVMGenericValueToInt(result, 1) as i32
+        };
+        execution_engine::LLVMDisposeGenericValue(result);
+        dispose_engine();
+        Ok(code)
+    }
+}
+
+const LINKERS: [&str; 2] = ["cc", "clang"];
+
+pub(crate) fn build_executable(module: prelude::LLVMModuleRef, output_path: &str, level: OptLevel) -> Result<(), ParseError> {
+    let object_path = format!("{output_path}.o");
+    emit_object(module, None, &object_path, level)?;
+    let r = link(&object_path, output_path);
+    let _ = fs::remove_file(&object_path);
+    r.map_err(|e| e.when(&format!("building executable {output_path}")))
+}
+
+fn link(object_path: &str, output_path: &str) -> Result<(), ParseError> {
+    for linker in LINKERS {
+        let output = match Command::new(linker).args([object_path, "-o", output_path]).output() {
+            Ok(output) => output,
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(e) => return Err(ParseET::EmitError(format!("could not run {linker}: {e}")).error())
+        };
+        if !output.status.success() {
+            return Err(ParseET::EmitError(format!("{linker} failed with {}:\n{}", output.status,
+                                                  String::from_utf8_lossy(&output.stderr).trim_end())).error())
+        }
+        return Ok(())
+    }
+    Err(ParseET::EmitError(format!("no linker found, tried {}", LINKERS.join(", "))).error())
+}
+
+fn emit_to_file(module: prelude::LLVMModuleRef, triple: Option<&str>, path: &str, file_type: LLVMCodeGenFileType, level: OptLevel) -> Result<(), ParseError> {
+    let machine = create_target_machine(module, triple, level)?;
+    let mut err = ptr::null_mut();
+    let failed = unsafe {
+        target_machine::LLVMTargetMachineEmitToFile(machine, module, c_str_ptr!(path) as *mut c_char, file_type, &mut err)
+    };
+    unsafe { target_machine::LLVMDisposeTargetMachine(machine) }
+    if failed != 0 {
+        return Err(ParseET::EmitError(take_message(err)).error())
+    }
+    Ok(())
+}
+
+pub(crate) fn set_host_target(module: prelude::LLVMModuleRef) -> Result<(), ParseError> {
+    let machine = create_target_machine(module, None, OptLevel::None)?;
+    unsafe { target_machine::LLVMDisposeTargetMachine(machine) }
+    Ok(())
+}
+
+fn create_target_machine(module: prelude::LLVMModuleRef, triple: Option<&str>, level: OptLevel) -> Result<LLVMTargetMachineRef, ParseError> {
+    unsafe {
+        if triple.is_some() {
+            target::LLVM_InitializeAllTargetInfos();
+            target::LLVM_InitializeAllTargets();
+            target::LLVM_InitializeAllTargetMCs();
+            target::LLVM_InitializeAllAsmPrinters();
+        } else if target::LLVM_InitializeNativeTarget() != 0 || target::LLVM_InitializeNativeAsmPrinter() != 0 {
+            return Err(ParseET::EmitError("could not initialize native target".to_string()).error())
+        }
+        let triple = match triple {
+            // disposed with LLVMDisposeMessage like the default triple, so llvm has to allocate it as well
+            Some(triple) => core::LLVMCreateMessage(c_str_ptr!(triple)),
+            None => target_machine::LLVMGetDefaultTargetTriple()
+        };
+        let mut t = ptr::null_mut();
+        let mut err = ptr::null_mut();
+        if target_machine::LLVMGetTargetFromTriple(triple, &mut t, &mut err) != 0 {
+            let e = take_message(err);
+            let triple = take_message(triple);
+            return Err(ParseET::EmitError(e).error().when(&format!("looking up target {triple}")))
+        }
+        let machine = target_machine::LLVMCreateTargetMachine(t, triple, c_str_ptr!(""), c_str_ptr!(""),
+                                                              level.codegen(),
+                                                              LLVMRelocMode::LLVMRelocPIC, // linkers default to PIE executables
+                                                              LLVMCodeModel::LLVMCodeModelDefault);
+        core::LLVMSetTarget(module, triple);
+        let layout = target_machine::LLVMCreateTargetDataLayout(machine);
+        target::LLVMSetModuleDataLayout(module, layout);
+        target::LLVMDisposeTargetData(layout);
+        core::LLVMDisposeMessage(triple);
+        Ok(machine)
+    }
+}
+
+pub(crate) fn emit_cfg_dot(module: prelude::LLVMModuleRef, function_name: &str) -> Result<String, ParseError> {
+    unsafe {
+        let function = core::LLVMGetNamedFunction(module, c_str_ptr!(function_name));
+        if function.is_null() {
+            return Err(ParseET::EmitError(format!("no function named `{function_name}`")).error().when("emitting control flow graph"))
+        }
+        let mut dot = format!("digraph \"{function_name}\" {{\n    node [shape=box];\n");
+        let mut block = core::LLVMGetFirstBasicBlock(function);
+        while !block.is_null() {
+            let name = block_name(block);
+            writeln!(dot, "    \"{name}\";").unwrap();
+            let term = core::LLVMGetBasicBlockTerminator(block);
+            if !term.is_null() {
+                let is_cond_br = core::LLVMGetInstructionOpcode(term) == LLVMOpcode::LLVMBr && core::LLVMIsConditional(term) != 0;
+                for i in 0..core::LLVMGetNumSuccessors(term) {
+                    let succ = block_name(core::LLVMGetSuccessor(term, i));
+                    if is_cond_br {
+                        writeln!(dot, "    \"{name}\" -> \"{succ}\" [label=\"{}\"];", i == 0).unwrap();
+                    } else {
+                        writeln!(dot, "    \"{name}\" -> \"{succ}\";").unwrap();
+                    }
+                }
+            }
+            block = core::LLVMGetNextBasicBlock(block);
+        }
+        dot.push_str("}\n");
+        Ok(dot)
+    }
+}
+
+fn block_name(block: prelude::LLVMBasicBlockRef) -> String {
+    unsafe { CStr::from_ptr(core::LLVMGetBasicBlockName(block)).to_string_lossy().into_owned() }
+}
+
+fn take_message(msg: *mut c_char) -> String {
+    if msg.is_null() {
+        return String::from("<no message>")
+    }
+    unsafe {
+        let s = CStr::from_ptr(msg).to_string_lossy().into_owned();
+        core::LLVMDisposeMessage(msg);
+        s
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::{compile, CompileOptions};
+    use crate::source::Source;
+    use crate::tokens::tok_iter::TokIter;
+    use crate::tokens::tokenize::tokenize;
+
+    #[test]
+    fn cfg_dot_has_an_edge_per_branch() {
+        let src = "fn f i32 with i32 n do
+    var i32 x is n
+    while x do
+        update x to call - with x literal i32 1 end
+    end
+    if x do
+        return literal i32 1
+    end
+    return literal i32 2
+end
+fn main i32 do
+    return call f with literal i32 3 end
+end
+";
+        let tokens = TokIter::new(tokenize(Source::from_string(src.to_string())).unwrap());
+        let module = compile(tokens, "cfg", &CompileOptions::default()).unwrap_or_else(|e| panic!("{e}"));
+        let dot = emit_cfg_dot(*module, "f").unwrap();
+        assert!(dot.starts_with("digraph \"f\" {"));
+        for edge in [
+            "\"entry\" -> \"cond.1\";",
+            "\"cond.1\" -> \"body.1\" [label=\"true\"];",
+            "\"cond.1\" -> \"whilecont.1\" [label=\"false\"];",
+            "\"body.1\" -> \"cond.1\";",
+            "\"whilecont.1\" -> \"then.4\" [label=\"true\"];",
+            "\"whilecont.1\" -> \"else.4\" [label=\"false\"];",
+            "\"else.4\" -> \"ifcont.4\";",
+        ] {
+            assert!(dot.contains(edge), "missing {edge} in\n{dot}");
+        }
+        // the returning then block has no successors
+        assert!(!dot.contains("\"then.4\" ->"));
+    }
+
+    fn tokens(src: &str) -> TokIter {
+        TokIter::new(tokenize(Source::from_string(src.to_string())).unwrap())
+    }
+
+    #[test]
+    fn emit_asm_writes_assembly_text() {
+        let module = compile(tokens("fn main i32 do\n    return literal i32 0\nend\n"), "asm", &CompileOptions::default()).unwrap();
+        let path = std::env::temp_dir().join(format!("minimal_language_asm_{}.s", std::process::id()));
+        emit_asm(*module, path.to_str().unwrap(), OptLevel::None).unwrap_or_else(|e| panic!("{e}"));
+        let asm = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert!(asm.contains("main:"), "{asm}");
+    }
+
+    #[test]
+    fn build_executable_links_a_runnable_program() {
+        let module = compile(tokens("fn main i32 do\n    return literal i32 3\nend\n"), "exe", &CompileOptions::default()).unwrap();
+        let path = std::env::temp_dir().join(format!("minimal_language_exe_{}", std::process::id()));
+        build_executable(*module, path.to_str().unwrap(), OptLevel::None).unwrap_or_else(|e| panic!("{e}"));
+        let status = Command::new(&path).status().unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!(status.code(), Some(3));
+    }
+
+    #[test]
+    fn emit_ir_to_file_writes_the_module() {
+        let module = compile(tokens("fn main i32 do\n    return literal i32 0\nend\n"), "ir", &CompileOptions::default()).unwrap();
+        let path = std::env::temp_dir().join(format!("minimal_language_ir_{}.ll", std::process::id()));
+        emit_ir_to_file(*module, path.to_str().unwrap()).unwrap_or_else(|e| panic!("{e}"));
+        let ir = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert!(ir.contains("ModuleID = 'ir'") && ir.contains("define i32 @main()"), "{ir}");
+    }
+
+    #[test]
+    fn verify_reports_a_block_without_terminator() {
+        unsafe {
+            let ctx = core::LLVMContextCreate();
+            let module = core::LLVMModuleCreateWithNameInContext(c_str_ptr!("broken"), ctx);
+            let fn_ty = core::LLVMFunctionType(core::LLVMVoidTypeInContext(ctx), ptr::null_mut(), 0, 0);
+            let f = core::LLVMAddFunction(module, c_str_ptr!("f"), fn_ty);
+            core::LLVMAppendBasicBlockInContext(ctx, f, c_str_ptr!("entry"));
+            let error = verify(module).err().map(|e| e.to_string());
+            core::LLVMDisposeModule(module);
+            core::LLVMContextDispose(ctx);
+            let error = error.expect("verified a block without terminator");
+            assert!(error.contains("Invalid module") && error.contains("terminator"), "{error}");
+        }
+    }
+
+    #[test]
+    fn jit_run_hands_the_module_back() {
+        let module = compile(tokens("fn main do\nend\n"), "jit", &CompileOptions::default()).unwrap();
+        // a second run only works if the first engine gave the module back
+        assert_eq!(jit_run(*module).unwrap(), 0);
+        assert_eq!(jit_run(*module).unwrap(), 0);
+    }
+
+    #[test]
+    fn emit_object_targets_the_triple() {
+        let module = compile(tokens("fn main i32 do\n    return literal i32 0\nend\n"), "obj", &CompileOptions::default()).unwrap();
+        let path = std::env::temp_dir().join(format!("minimal_language_obj_{}.o", std::process::id()));
+        emit_object(*module, Some("x86_64-pc-windows-msvc"), path.to_str().unwrap(), OptLevel::None).unwrap_or_else(|e| panic!("{e}"));
+        let object = fs::read(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        // a coff object starts with the x86_64 machine type rather than an elf or mach-o magic
+        assert_eq!(object[..2], [0x64, 0x86]);
+    }
+}
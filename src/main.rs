@@ -2,10 +2,12 @@
 #![feature(try_blocks)]
 #![feature(stmt_expr_attributes)]
 
+use std::collections::HashMap;
+use std::path::Path;
 use std::process::Command;
 use llvm_sys::bit_writer;
-use llvm_sys::core;
-use crate::compiler::compile;
+use crate::compiler::{compile, CompileOptions};
+use crate::emit::{build_executable, emit_asm, emit_ir_to_file, jit_run, OptLevel};
 use crate::source::{ParseError, Source};
 use crate::tokens::tok_iter::TokIter;
 use crate::tokens::tokenize::tokenize;
@@ -13,6 +15,7 @@ use crate::tokens::tokenize::tokenize;
 mod tokens;
 mod source;
 mod compiler;
+mod emit;
 
 #[macro_export]
 macro_rules! c_str {
@@ -34,37 +37,93 @@ macro_rules! c_str_ptr {
 }
 
 fn main() {
-    match compile_full("testing/fibonacci") {
+    let src = parse_source(std::env::args().skip(1))
+        .expect("usage: minimal_language <file>[.mi] [-D NAME=value] [-O0|-O1|-O2|-O3] [--jit] [--profile] [--prelude] [-Werror]");
+    let options = CompileOptions {
+        defines: parse_defines(std::env::args().skip(1)),
+        profile: std::env::args().any(|arg| arg == "--profile"),
+        prelude: std::env::args().any(|arg| arg == "--prelude"),
+        warnings_as_errors: std::env::args().any(|arg| arg == "-Werror"),
+        opt_level: parse_opt_level(std::env::args().skip(1)),
+    };
+    if std::env::args().any(|arg| arg == "--jit") {
+        match jit_file(&src, &options) {
+            Ok(code) => println!("executed with {code}"),
+            Err(e) => panic!("{}\n{:?}", e, e)
+        }
+        return
+    }
+    match compile_full(&src, &options) {
         Ok(_) => (),
         Err(e) => panic!("{}\n{:?}", e, e)
     }
     println!();
-    let code = Command::new("testing/fibonacci.exe")
+    let code = Command::new(Path::new(".").join(src + ".exe"))
         .spawn().unwrap().wait().unwrap();
     println!("executed with {code}");
 }
 
-fn compile_full(src: &str) -> Result<(), ParseError>{
+/// the first argument that is no flag, without its `.mi` extension
+fn parse_source(mut args: impl Iterator<Item=String>) -> Option<String> {
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            // the define follows as an argument of its own
+            "-D" => { args.next(); }
+            a if a.starts_with('-') => (),
+            a => return Some(a.strip_suffix(".mi").unwrap_or(a).to_string())
+        }
+    }
+    None
+}
+
+/// collects `-D NAME=value` and `-DNAME=value` arguments, a define without value is set to `1`
+fn parse_defines(mut args: impl Iterator<Item=String>) -> HashMap<String, String> {
+    let mut defines = HashMap::new();
+    while let Some(arg) = args.next() {
+        let define = match arg.strip_prefix("-D") {
+            Some("") => args.next().unwrap_or_default(),
+            Some(d) => d.to_string(),
+            None => continue
+        };
+        match define.split_once('=') {
+            Some((name, value)) => defines.insert(name.to_string(), value.to_string()),
+            None => defines.insert(define, String::from("1"))
+        };
+    }
+    defines
+}
+
+/// the last `-O0` to `-O3` argument, no optimization without one
+fn parse_opt_level(args: impl Iterator<Item=String>) -> OptLevel {
+    args.filter_map(|arg| match arg.as_str() {
+        "-O0" => Some(OptLevel::None),
+        "-O1" => Some(OptLevel::Less),
+        "-O2" => Some(OptLevel::Default),
+        "-O3" => Some(OptLevel::Aggressive),
+        _ => None
+    }).last().unwrap_or(OptLevel::None)
+}
+
+/// compiles `src` and runs it in a jit instead of writing any files
+fn jit_file(src: &str, options: &CompileOptions) -> Result<i32, ParseError> {
+    let name = src.split("/").last().unwrap();
+    let source = Source::from_file(src.to_string() + ".mi").expect("Could not read source file");
+    let tokens = tokenize(source)?;
+    let module = compile(TokIter::new(tokens), name, options)?;
+    jit_run(*module)
+}
+
+fn compile_full(src: &str, options: &CompileOptions) -> Result<(), ParseError>{
     let name = src.split("/").last().unwrap();
     let source = Source::from_file(src.to_string() + ".mi").expect("Could not read source file");
     let tokens = tokenize(source)?;
-    let module = compile(TokIter::new(tokens), name)?;
+    let module = compile(TokIter::new(tokens), name, options)?;
     let bitcode_file = src.to_string() + ".bc";
-    let success = unsafe { bit_writer::LLVMWriteBitcodeToFile(module, c_str_ptr!(bitcode_file)) };
+    let success = unsafe { bit_writer::LLVMWriteBitcodeToFile(*module, c_str_ptr!(bitcode_file)) };
     println!("wrote to file with exit code: {success}");
-    println!();
-    unsafe { core::LLVMDumpModule(module) }
-    println!();
-    unsafe { core::LLVMDisposeModule(module) }
-    let dis_code = Command::new("C:/LLVM/llvm-project/build/Release/bin/llvm-dis.exe")
-        .args([bitcode_file.clone()])
-        .spawn().unwrap().wait().unwrap();
-    println!("disassembled .bc to .ll with {dis_code}");
-    println!();
-    let compile_code = Command::new("C:/LLVM/llvm-project/build/Release/bin/clang.exe")
-        .args([bitcode_file, "-v".to_string(), "-o".to_string(), src.to_string() + ".exe"])
-        .spawn().unwrap().wait().unwrap();
-    println!();
-    println!("compiled to binary with {compile_code}");
+    emit_ir_to_file(*module, &(src.to_string() + ".ll"))?;
+    emit_asm(*module, &(src.to_string() + ".s"), options.opt_level)?;
+    build_executable(*module, &(src.to_string() + ".exe"), options.opt_level)?;
+    println!("compiled to binary");
     Ok(())
 }
\ No newline at end of file
@@ -1,3 +1,4 @@
+use std::cell::Cell;
 use std::fmt::{Debug, Display, Formatter};
 use std::fs::File;
 use std::io::{Read};
@@ -196,6 +197,12 @@ impl Span {
         self.end = usize::max(self.end, p.1);
     }
 
+    /// the source bytes the span covers, both ends inclusive.
+    /// the tokenizer works on bytes, so a span may start or end inside a multi-byte char
+    pub(crate) fn bytes(&self) -> &[u8] {
+        &self.source.source.as_bytes()[self.start..=self.end]
+    }
+
     pub(crate) fn render_span_code(&self, line_pad: usize) -> String {
         let (sl, sp) = self.start().pos();
         let (el, ep) = self.end().pos();
@@ -242,6 +249,20 @@ impl ParseError {
         self.loc = Some(loc);
         self
     }
+    /// reports the error without aborting compilation
+    pub(crate) fn warn(self) {
+        WARNINGS.with(|w| w.set(w.get() + 1));
+        eprintln!("warning: {}", self)
+    }
+}
+
+thread_local! {
+    static WARNINGS: Cell<usize> = Cell::new(0);
+}
+
+/// number of warnings reported on this thread so far
+pub(crate) fn warning_count() -> usize {
+    WARNINGS.with(|w| w.get())
 }
 
 impl From<std::io::Error> for ParseError {
@@ -259,6 +280,18 @@ pub(crate) enum ParseET {
     ParseError(String, String),
     ParseLiteralError(Literal, String),
     VariableError(String),
+    NotCallable(String),
+    EmitError(String),
+    VerificationError(String),
+    LinkError(String),
+    Internal(String),
+    Redeclaration(String),
+    DuplicateDefinition(String),
+    InfiniteRecursion(String),
+    UnreachableCode,
+    UnusedResult,
+    MissingReturn(String),
+    WarningsAsErrors(usize),
 }
 
 impl ParseET {
@@ -295,6 +328,18 @@ impl Display for ParseError {
                        Literal::Bool(_) => "Float",
                    }, e),
                    ParseET::VariableError(e) => format!("cant find variable:\n    {e}"),
+                   ParseET::NotCallable(name) => format!("not callable:\n    `{name}` is not a function"),
+                   ParseET::EmitError(e) => format!("Emit error:\n    {e}"),
+                   ParseET::VerificationError(e) => format!("Invalid module:\n    {}", e.trim_end().replace('\n', "\n    ")),
+                   ParseET::LinkError(e) => format!("Link error:\n    {}", e.replace('\n', "\n    ")),
+                   ParseET::Internal(e) => format!("Internal compiler error:\n    {e}"),
+                   ParseET::Redeclaration(name) => format!("Redeclaration:\n    `{name}` is already declared in this block and gets shadowed"),
+                   ParseET::DuplicateDefinition(name) => format!("Duplicate definition:\n    `{name}` is already defined in this module"),
+                   ParseET::InfiniteRecursion(name) => format!("Infinite recursion:\n    `{name}` calls itself before any statement that could end the recursion"),
+                   ParseET::UnreachableCode => format!("Unreachable code:\n    statement follows a return, break, continue or unreachable in the same block"),
+                   ParseET::UnusedResult => "Unused result:\n    the value of this statement is never used".to_string(),
+                   ParseET::MissingReturn(name) => format!("Missing return:\n    `{name}` can reach its end without returning a value"),
+                   ParseET::WarningsAsErrors(count) => format!("Warnings as errors:\n    {count} warnings were reported"),
                },
                if self.context.len() > 0 {
                    format!("\n    while {}", self.context.join("\n    while "))
@@ -1,3 +1,4 @@
 pub(crate) mod tokens;
 pub(crate) mod tok_iter;
-pub(crate) mod tokenize;
\ No newline at end of file
+pub(crate) mod tokenize;
+pub(crate) mod symbol;
\ No newline at end of file
@@ -0,0 +1,102 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{Debug, Display, Formatter};
+use std::rc::Rc;
+
+/// Interned identifier, cheap to copy, hash and compare.
+/// The table owns the backing strings and shares them with [`Symbol::as_str`], they are freed with the thread.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct Symbol(u32);
+
+#[derive(Default)]
+struct Interner {
+    symbols: HashMap<Rc<str>, Symbol>,
+    strings: Vec<Rc<str>>
+}
+
+thread_local! {
+    static INTERNER: RefCell<Interner> = RefCell::new(Interner::default());
+}
+
+impl Symbol {
+    pub(crate) fn intern(s: &str) -> Self {
+        INTERNER.with(|i| {
+            let mut i = i.borrow_mut();
+            if let Some(sym) = i.symbols.get(s) {
+                return *sym
+            }
+            let s: Rc<str> = Rc::from(s);
+            let sym = Symbol(i.strings.len() as u32);
+            i.strings.push(s.clone());
+            i.symbols.insert(s, sym);
+            sym
+        })
+    }
+
+    pub(crate) fn as_str(self) -> Rc<str> {
+        INTERNER.with(|i| i.borrow().strings[self.0 as usize].clone())
+    }
+}
+
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        *self.as_str() == *other
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        *self.as_str() == **other
+    }
+}
+
+impl Display for Symbol {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl Debug for Symbol {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::time::Instant;
+    use super::*;
+
+    #[test]
+    fn interning_gives_one_id_per_string() {
+        let a = Symbol::intern("interned_a");
+        assert_eq!(a, Symbol::intern(&String::from("interned_a")));
+        assert_ne!(a, Symbol::intern("interned_b"));
+        assert_eq!(&*a.as_str(), "interned_a");
+        assert_eq!(std::mem::size_of::<Symbol>(), std::mem::size_of::<u32>());
+    }
+
+    /// `cargo test --release -- --ignored --nocapture symbol_lookups` prints the timings
+    #[test]
+    #[ignore]
+    fn bench_symbol_lookups() {
+        let names = (0..10_000).map(|i| format!("identifier_{i}")).collect::<Vec<_>>();
+        let by_string = names.iter().enumerate().map(|(i, n)| (n.clone(), i)).collect::<HashMap<_, _>>();
+        let by_symbol = names.iter().enumerate().map(|(i, n)| (Symbol::intern(n), i)).collect::<HashMap<_, _>>();
+        let symbols = names.iter().map(|n| Symbol::intern(n)).collect::<Vec<_>>();
+        let start = Instant::now();
+        let mut sum = 0;
+        for _ in 0..100 {
+            sum += names.iter().map(|n| by_string[n]).sum::<usize>();
+        }
+        let strings = start.elapsed();
+        let start = Instant::now();
+        for _ in 0..100 {
+            sum -= symbols.iter().map(|s| by_symbol[s]).sum::<usize>();
+        }
+        let ids = start.elapsed();
+        assert_eq!(sum, 0);
+        println!("1M lookups: String keys {strings:?}, Symbol keys {ids:?}");
+    }
+}
@@ -2,16 +2,26 @@ use std::str::FromStr;
 use std::str::pattern::Pattern;
 use std::u128;
 use crate::source::{OnParseErr, ParseError, ParseET, Source, SourceIter, Span};
+use crate::tokens::symbol::Symbol;
 use crate::tokens::tokens::{Literal, NumLit, NumLitTy, Token, TokenType};
 
 pub(crate) fn tokenize(source: Source) -> Result<Vec<Token>, ParseError>{
+    tokenize_with(source, false)
+}
+
+/// like [`tokenize`] but whitespace and comments are kept as [`TokenType::Trivia`],
+/// so the spans of the tokens cover the source without gaps and their text concatenates back to it
+pub(crate) fn tokenize_with_trivia(source: Source) -> Result<Vec<Token>, ParseError>{
+    tokenize_with(source, true)
+}
+
+fn tokenize_with(source: Source, keep_trivia: bool) -> Result<Vec<Token>, ParseError>{
     let mut iter = SourceIter::new(source);
     let mut tokens = vec![];
     while iter.left() > 0 {
         match iter.this()? {
             '"' => {
-                let (string, span) = collect_until(&mut iter, true, true,
-                                                   |c| c.map(|c|c != '"').unwrap_or(false))
+                let (string, span) = collect_string(&mut iter)
                     .e_when("tokenizing string literal".to_string())?;
                 tokens.push(TokenType::Literal(Literal::String(string)).at(span));
             }
@@ -46,13 +56,14 @@ pub(crate) fn tokenize(source: Source) -> Result<Vec<Token>, ParseError>{
                 r.e_when(String::from("tokenizing comment"))?;
             }
             '\'' => {
-                let (char_src, span) = collect_until(&mut iter, true, true,
-                                                   |c| c.map(|c|c != '\'').unwrap_or(false))
+                let (char_src, span) = collect_char(&mut iter)
                     .e_when("tokenizing char literal".to_string())?;
-                if char_src.len() != 1 {
-                    return Err(ParseET::TokenizationError(format!("Expected char, found: '{}'", char_src)).at(span))
-                }
-                let char = char_src.chars().nth(0).unwrap();
+                let char = match (char_src.chars().count(), char_src.strip_prefix('\\')) {
+                    (1, None) => char_src.chars().nth(0).unwrap(),
+                    (2, Some(escape)) => unescape(escape.chars().nth(0).unwrap())
+                        .ok_or_else(|| ParseET::TokenizationError(format!("unknown escape sequence: '{}'", char_src)).at(span.clone()))?,
+                    _ => return Err(ParseET::TokenizationError(format!("Expected char, found: '{}'", char_src)).at(span))
+                };
                 tokens.push(TokenType::Literal(Literal::Char(char)).at(span));
             }
             c if c.is_whitespace() => {
@@ -65,15 +76,13 @@ pub(crate) fn tokenize(source: Source) -> Result<Vec<Token>, ParseError>{
                 tokens.push(match ident {
                     ident if &ident == "true" => TokenType::Literal(Literal::Bool(true)),
                     ident if &ident == "false" => TokenType::Literal(Literal::Bool(false)),
-                    ident => TokenType::Ident(ident)
+                    ident => TokenType::Ident(Symbol::intern(&ident))
                 }.at(span));
             }
             c if c.is_ascii_digit() => {
-                iter.index -= 1;
-                let (num, span) = collect_until(&mut iter, true, true,
-                                                   |c| c.map(|c| c.is_ascii_alphanumeric() || c == '_').unwrap_or(false))
+                let (num, span) = collect_until(&mut iter, false, false,
+                                                   |c| c.map(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.').unwrap_or(false))
                     .e_when("tokenizing number literal".to_string())?;
-                iter.index -= 1;
                 let (lit, ty) = str_to_num_lit(num).e_at(span.clone())?;
                 tokens.push(TokenType::Literal(Literal::Number(lit, ty)).at(span));
             }
@@ -83,9 +92,93 @@ pub(crate) fn tokenize(source: Source) -> Result<Vec<Token>, ParseError>{
         }
         iter.next();
     }
+    if keep_trivia {
+        tokens = fill_trivia(&mut iter, tokens);
+    }
     Ok(tokens)
 }
 
+/// inserts a trivia token for every stretch of source not covered by a token
+fn fill_trivia(iter: &mut SourceIter, tokens: Vec<Token>) -> Vec<Token> {
+    let mut filled = vec![];
+    let mut covered = 0;
+    for tok in tokens {
+        if tok.loc.start > covered {
+            filled.push(trivia(iter, covered, tok.loc.start));
+        }
+        covered = tok.loc.end + 1;
+        filled.push(tok);
+    }
+    if iter.len() > covered {
+        filled.push(trivia(iter, covered, iter.len()));
+    }
+    filled
+}
+
+fn trivia(iter: &mut SourceIter, from: usize, to: usize) -> Token {
+    iter.index = from;
+    let start = iter.here();
+    iter.index = to - 1;
+    let span = Span::from_points(start, iter.here());
+    TokenType::Trivia(String::from_utf8_lossy(span.bytes()).into_owned()).at(span)
+}
+
+/// the character an escape sequence `\<c>` stands for
+fn unescape(c: char) -> Option<char> {
+    Some(match c {
+        'n' => '\n',
+        't' => '\t',
+        'r' => '\r',
+        '0' => '\0',
+        '\\' => '\\',
+        '\'' => '\'',
+        '"' => '"',
+        _ => return None
+    })
+}
+
+/// the contents of the string literal starting at the opening quote, with escape sequences replaced.
+/// like [`collect_until`] it stops on the closing quote
+fn collect_string(iter: &mut SourceIter) -> Result<(String, Span), ParseError> {
+    let start = iter.here();
+    let mut result = String::new();
+    iter.next();
+    loop {
+        match iter.this()? {
+            '"' => break,
+            '\\' => {
+                iter.next();
+                let c = iter.this()?;
+                result.push(unescape(c).ok_or_else(|| ParseET::TokenizationError(format!("unknown escape sequence: '\\{c}'"))
+                    .at(Span::from_points(start.clone(), iter.here())))?);
+            }
+            c => result.push(c)
+        }
+        iter.next();
+    }
+    Ok((result, Span::from_points(start, iter.here())))
+}
+
+/// the source between the quotes of a char literal with escapes kept as written, so an escaped quote does not end it
+fn collect_char(iter: &mut SourceIter) -> Result<(String, Span), ParseError> {
+    let start = iter.here();
+    let mut result = String::new();
+    iter.next();
+    loop {
+        match iter.this()? {
+            '\'' => break,
+            '\\' => {
+                result.push('\\');
+                iter.next();
+                result.push(iter.this()?);
+            }
+            c => result.push(c)
+        }
+        iter.next();
+    }
+    Ok((result, Span::from_points(start, iter.here())))
+}
+
 fn collect_until(iter: &mut SourceIter, skip_first: bool, consume_break: bool, cond: fn(Option<char>) -> bool) -> Result<(String, Span), ParseError>{
     let start = iter.here();
     let mut result = String::new();
@@ -178,4 +271,55 @@ pub(crate) fn str_to_num_lit(mut num: String) -> Result<(NumLit, Option<NumLitTy
         )
     }?;
     Ok((lit, ty))
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use super::*;
+
+    fn round_trip(source: Source) -> String {
+        let bytes = tokenize_with_trivia(source).unwrap_or_else(|e| panic!("{e}"))
+            .iter().flat_map(|tok| tok.loc.bytes().to_vec()).collect();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn trivia_round_trips_the_examples() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/testing");
+        let mut checked = 0;
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().map_or(true, |e| e != "mi") {
+                continue
+            }
+            // from a string, since a file source has its includes expanded
+            let text = fs::read_to_string(&path).unwrap();
+            assert_eq!(round_trip(Source::from_string(text.clone())), text, "{}", path.display());
+            checked += 1;
+        }
+        assert!(checked > 0);
+    }
+
+    #[test]
+    fn trivia_round_trips_comments_and_escapes() {
+        let text = "// line comment\nconst ptr S is \"tab\\t quote\\\" newline\\n\" /* block\n comment */\nfn main do\n    call puts with S end // trailing\nend";
+        assert_eq!(round_trip(Source::from_string(text.to_string())), text);
+    }
+
+    #[test]
+    fn escaped_quotes_do_not_end_char_literals() {
+        let tokens = tokenize(Source::from_string("'\\'' '\\\\' 'a'".to_string())).unwrap_or_else(|e| panic!("{e}"));
+        let chars = tokens.iter().map(|tok| match &tok.tt {
+            TokenType::Literal(Literal::Char(c)) => *c,
+            tt => panic!("{tt:?}")
+        }).collect::<String>();
+        assert_eq!(chars, "'\\a");
+    }
+
+    #[test]
+    fn trivia_round_trips_non_ascii() {
+        // `à` outside of a comment is split into a particle and a whitespace byte
+        let text = "// grüße → ü\nfn main do\n    /* ñ */ à\nend";
+        assert_eq!(round_trip(Source::from_string(text.to_string())), text);
+    }
+}
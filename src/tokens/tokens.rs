@@ -1,5 +1,6 @@
 use std::fmt::{Debug, Display, Formatter};
 use crate::source::Span;
+use crate::tokens::symbol::Symbol;
 
 #[derive(Clone, PartialEq)]
 pub(crate) struct Token {
@@ -21,8 +22,10 @@ pub(crate) type glued = bool;
 #[derive(Debug, Clone, PartialEq)]
 pub(crate) enum TokenType {
     Particle(char, glued),
-    Ident(String),
-    Literal(Literal)
+    Ident(Symbol),
+    Literal(Literal),
+    /// whitespace and comments, only produced by [`tokenize_with_trivia`](crate::tokens::tokenize::tokenize_with_trivia)
+    Trivia(String)
 }
 
 impl TokenType {